@@ -0,0 +1,85 @@
+//! Compares mutex-based futures with the lock-free ones
+//!
+//! Run with `cargo bench`
+extern crate rotor_test;
+extern crate rotor_tools;
+
+use std::time::{Instant, Duration};
+
+use rotor_test::MockLoop;
+use rotor_tools::future::{new, oneshot, MakeFuture};
+
+// Every put sends a wakeup to the (never running) loop, so we create a new
+// mock loop every batch to keep notification queue from overflowing
+const BATCH: u32 = 1000;
+const BATCHES: u32 = 100;
+const POLLS: u32 = 1_000_000;
+
+
+fn report(name: &str, total: Duration, iterations: u32) {
+    let nanos = total.as_secs() * 1_000_000_000
+        + total.subsec_nanos() as u64;
+    println!("{:<24} {:>8} ns/iter", name, nanos / iterations as u64);
+}
+
+fn roundtrip_mutex() -> Duration {
+    let mut total = Duration::new(0, 0);
+    for _ in 0..BATCHES {
+        let mut lp = MockLoop::new(());
+        let scope = &mut lp.scope(1);
+        let start = Instant::now();
+        for i in 0..BATCH {
+            let arc = new(&mut *scope, |x: u32| x);
+            let future = arc.clone().make_future();
+            arc.lock().unwrap().put(i);
+            assert!(future.is_done());
+            assert_eq!(future.consume().ok().unwrap(), Ok(i));
+        }
+        total += start.elapsed();
+    }
+    total
+}
+
+fn roundtrip_oneshot() -> Duration {
+    let mut total = Duration::new(0, 0);
+    for _ in 0..BATCHES {
+        let mut lp = MockLoop::new(());
+        let scope = &mut lp.scope(1);
+        let start = Instant::now();
+        for i in 0..BATCH {
+            let (slot, future) = oneshot(&mut *scope);
+            slot.put(i).unwrap();
+            assert!(future.is_done());
+            assert_eq!(future.consume().ok().unwrap(), Ok(i));
+        }
+        total += start.elapsed();
+    }
+    total
+}
+
+fn poll_mutex() -> Duration {
+    let mut lp = MockLoop::new(());
+    let future = new(&mut lp.scope(1), |x: u32| x).make_future();
+    let start = Instant::now();
+    for _ in 0..POLLS {
+        assert!(!future.is_done());
+    }
+    start.elapsed()
+}
+
+fn poll_oneshot() -> Duration {
+    let mut lp = MockLoop::new(());
+    let (_slot, future) = oneshot::<u32, _>(&mut lp.scope(1));
+    let start = Instant::now();
+    for _ in 0..POLLS {
+        assert!(!future.is_done());
+    }
+    start.elapsed()
+}
+
+fn main() {
+    report("roundtrip/mutex", roundtrip_mutex(), BATCH*BATCHES);
+    report("roundtrip/oneshot", roundtrip_oneshot(), BATCH*BATCHES);
+    report("poll/mutex", poll_mutex(), POLLS);
+    report("poll/oneshot", poll_oneshot(), POLLS);
+}
@@ -0,0 +1,89 @@
+//! Derive macros for rotor-tools
+//!
+//! Use them through the `derive` feature of `rotor-tools`, rather than
+//! depending on this crate directly.
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate syn;
+#[macro_use] extern crate quote;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{DeriveInput, Data, Fields, Ident, Variant};
+
+
+/// Derives `rotor_tools::sync::Replaceable` for an enum
+///
+/// The unit variant marked with `#[replaceable(restart)]` is the state
+/// returned from `restart()`. If no variant is marked, the default
+/// `restart()` is used (which panics).
+///
+/// ```ignore
+/// #[derive(Replaceable)]
+/// enum Fsm {
+///     #[replaceable(restart)]
+///     Idle,
+///     Connected(Connection),
+/// }
+/// ```
+#[proc_macro_derive(Replaceable, attributes(replaceable))]
+pub fn derive_replaceable(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match replaceable(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn replaceable(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let data = match input.data {
+        Data::Enum(ref data) => data,
+        _ => return Err(syn::Error::new(Span::call_site(),
+            "Replaceable can only be derived for enums")),
+    };
+    let mut restart = None;
+    for variant in &data.variants {
+        for attr in &variant.attrs {
+            if !attr.path().is_ident("replaceable") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("restart") {
+                    return Err(meta.error("expected `restart`"));
+                }
+                if restart.is_some() {
+                    return Err(meta.error("duplicate restart variant"));
+                }
+                restart = Some(unit_variant(variant)?);
+                Ok(())
+            })?;
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    let restart = restart.map(|variant| quote! {
+        fn restart(_previous: ::std::option::Option<Self>,
+            _scope: &mut ::rotor::Scope<<Self as ::rotor::Machine>::Context>)
+            -> ::rotor::Response<Self, <Self as ::rotor::Machine>::Seed>
+        {
+            ::rotor::Response::ok(#name::#variant)
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::rotor_tools::sync::Replaceable
+            for #name #ty_generics #where_clause
+        {
+            #restart
+        }
+    })
+}
+
+fn unit_variant(variant: &Variant) -> syn::Result<Ident> {
+    match variant.fields {
+        Fields::Unit => Ok(variant.ident.clone()),
+        _ => Err(syn::Error::new_spanned(variant,
+            "only unit variants can be used by #[replaceable]")),
+    }
+}
@@ -0,0 +1,351 @@
+use std::io;
+use std::error::Error;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use rotor::mio::{EventSet, PollOpt, Evented, TryAccept};
+use rotor::void::Void;
+use rotor::{Machine, Scope, Response, Time, SpawnError};
+
+use shutdown::{Shutdown, Registration};
+use super::{Spawner, Children, Rejection};
+
+// Same values on linux, BSD and OS X
+const ENFILE: i32 = 23;
+const EMFILE: i32 = 24;
+
+/// Time to wait when resources for new connection are exhausted, i.e. file
+/// descriptors or slab space
+const BACKOFF_DELAY_MS: u64 = 100;
+
+
+/// A state machine created for every accepted connection of `Accept`
+///
+/// The `S` is the type of the accepted socket, i.e. `TcpStream` for TCP or
+/// `UnixStream` for unix sockets.
+pub trait Accepted<S>: Machine<Seed=Void> {
+    /// Creates the state machine for the accepted socket
+    ///
+    /// This is the place for per-connection setup, e.g. setting socket
+    /// options or checking the peer address.
+    fn accepted(sock: S, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Void>;
+
+    /// Same as `Spawner::filter`, e.g. may reject banned peer addresses
+    fn filter(_sock: &S, _scope: &mut Scope<Self::Context>)
+        -> Result<(), Box<dyn Error>>
+    {
+        Ok(())
+    }
+    /// Called when `accept()` fails
+    ///
+    /// Returns the time to wait before accepting connections again, or
+    /// `None` to continue accepting immediately. Default implementation
+    /// waits 100 ms when limit of file descriptors is reached (`EMFILE`,
+    /// `ENFILE`), as connections can't be accepted until some are closed.
+    fn accept_error(err: &io::Error, _scope: &mut Scope<Self::Context>)
+        -> Option<Duration>
+    {
+        match err.raw_os_error() {
+            Some(ENFILE) | Some(EMFILE) => {
+                Some(Duration::from_millis(BACKOFF_DELAY_MS))
+            }
+            _ => None,
+        }
+    }
+    /// Same as `Spawner::children`
+    fn children(_context: &mut Self::Context) -> Option<&mut Children> {
+        None
+    }
+    /// Same as `Spawner::shutdown`
+    ///
+    /// Also, the `Accept` machine stops (and closes the listening socket)
+    /// when shutdown is requested.
+    fn shutdown(_context: &mut Self::Context) -> Option<&Shutdown> {
+        None
+    }
+}
+
+enum State {
+    Accepting,
+    Paused,
+    Backoff(Time),
+}
+
+/// A spawner which accepts connections on a listening socket
+///
+/// The `L` is a listener, i.e. `TcpListener` or `UnixListener`. The `C`
+/// is the state machine created for each connection. Use it as
+/// `Spawn<Accept<L, C>>`:
+///
+/// ```ignore
+/// let listener = TcpListener::bind(&addr).unwrap();
+/// loop_inst.add_machine_with(|scope| {
+///     Accept::<TcpListener, Connection>::new(listener, scope)
+///         .wrap(Spawn::Spawner)
+/// }).unwrap();
+/// ```
+pub struct Accept<L, C> {
+    listener: L,
+    state: State,
+    registration: Option<Registration>,
+    phantom: PhantomData<fn() -> C>,
+}
+
+impl<L, C> Accept<L, C>
+    where L: TryAccept + Evented,
+          C: Accepted<L::Output>,
+{
+    pub fn new(listener: L, scope: &mut Scope<C::Context>)
+        -> Response<Accept<L, C>, Void>
+    {
+        let notifier = scope.notifier();
+        let registration = C::shutdown(&mut **scope)
+            .map(|s| s.register(notifier));
+        let me = Accept {
+            listener,
+            state: State::Accepting,
+            registration,
+            phantom: PhantomData,
+        };
+        me.register(scope)
+    }
+    pub fn listener(&self) -> &L {
+        &self.listener
+    }
+    fn register<S>(mut self, scope: &mut Scope<C::Context>)
+        -> Response<Self, S>
+    {
+        match scope.register(&self.listener,
+            EventSet::readable(), PollOpt::level())
+        {
+            Ok(()) => {
+                self.state = State::Accepting;
+                Response::ok(self)
+            }
+            Err(e) => Response::error(Box::new(e)),
+        }
+    }
+    fn accept(self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        match self.state {
+            State::Accepting => {}
+            _ => return self.idle(),
+        }
+        match self.listener.accept() {
+            Ok(Some(sock)) => Response::spawn(self, sock),
+            Ok(None) => Response::ok(self),
+            Err(e) => match C::accept_error(&e, scope) {
+                Some(delay) => self.backoff(delay, scope),
+                None => Response::ok(self),
+            },
+        }
+    }
+    fn backoff(mut self, delay: Duration, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        if let State::Accepting = self.state {
+            scope.deregister(&self.listener).ok();
+        }
+        let deadline = scope.now() + delay;
+        self.state = State::Backoff(deadline);
+        Response::ok(self).deadline(deadline)
+    }
+    fn idle(self) -> Response<Self, L::Output> {
+        match self.state {
+            State::Backoff(deadline) => Response::ok(self).deadline(deadline),
+            _ => Response::ok(self),
+        }
+    }
+}
+
+impl<L, C> Spawner for Accept<L, C>
+    where L: TryAccept + Evented,
+          C: Accepted<L::Output>,
+{
+    type Child = C;
+    type Seed = L::Output;
+
+    fn spawn(sock: L::Output, scope: &mut Scope<C::Context>)
+        -> Response<C, Void>
+    {
+        C::accepted(sock, scope)
+    }
+    fn filter(sock: &L::Output, scope: &mut Scope<C::Context>)
+        -> Result<(), Box<dyn Error>>
+    {
+        C::filter(sock, scope)
+    }
+    fn children(context: &mut C::Context) -> Option<&mut Children> {
+        C::children(context)
+    }
+    fn shutdown(context: &mut C::Context) -> Option<&Shutdown> {
+        C::shutdown(context)
+    }
+    fn pause(mut self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        if let State::Accepting = self.state {
+            scope.deregister(&self.listener).ok();
+        }
+        self.state = State::Paused;
+        Response::ok(self)
+    }
+    fn resume(self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        match self.state {
+            State::Paused => self.register(scope),
+            _ => self.idle(),
+        }
+    }
+    fn rejected(self, _reason: Rejection, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        self.accept(scope)
+    }
+}
+
+impl<L, C> Machine for Accept<L, C>
+    where L: TryAccept + Evented,
+          C: Accepted<L::Output>,
+{
+    type Context = C::Context;
+    type Seed = L::Output;
+
+    fn create(_seed: L::Output, _scope: &mut Scope<C::Context>)
+        -> Response<Self, Void>
+    {
+        unreachable!("Accept is created with Accept::new");
+    }
+    fn ready(self, _events: EventSet, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        self.accept(scope)
+    }
+    fn spawned(self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        self.accept(scope)
+    }
+    fn timeout(self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        match self.state {
+            State::Backoff(deadline) if scope.now() >= deadline => {
+                self.register(scope)
+            }
+            _ => self.idle(),
+        }
+    }
+    fn wakeup(self, scope: &mut Scope<C::Context>)
+        -> Response<Self, L::Output>
+    {
+        let shutdown = match C::shutdown(&mut **scope) {
+            Some(shutdown) => shutdown.is_requested(),
+            None => false,
+        };
+        if self.registration.is_some() && shutdown {
+            return Response::done();
+        }
+        self.idle()
+    }
+    fn spawn_error(self, scope: &mut Scope<C::Context>,
+        _error: SpawnError<L::Output>)
+        -> Response<Self, L::Output>
+    {
+        // the connection is dropped, and we wait for some machines to finish
+        self.backoff(Duration::from_millis(BACKOFF_DELAY_MS), scope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use std::net::{SocketAddr, TcpStream as StdStream};
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::mio::tcp::{TcpListener, TcpStream};
+    use rotor::void::{Void, unreachable};
+
+    use compose::Spawn;
+    use shutdown::Shutdown;
+    use super::{Accept, Accepted};
+    use util::test::machine;
+
+    struct Context {
+        shutdown: Shutdown,
+    }
+
+    struct Connection(SocketAddr);
+
+    impl Accepted<TcpStream> for Connection {
+        fn accepted(sock: TcpStream, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Connection(sock.peer_addr().unwrap()))
+        }
+        fn shutdown(context: &mut Context) -> Option<&Shutdown> {
+            Some(&context.shutdown)
+        }
+    }
+
+    impl Machine for Connection {
+        type Context = Context;
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            unreachable!();
+        }
+    }
+
+    type Listener = Accept<TcpListener, Connection>;
+
+    #[test]
+    fn test_accept() {
+        let mut lp = rotor_test::MockLoop::new(Context {
+            shutdown: Shutdown::new(),
+        });
+        let sock = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = sock.local_addr().unwrap();
+        let acc = machine(Listener::new(sock, &mut lp.scope(1)));
+        assert_eq!(lp.ctx().shutdown.live(), 1);
+
+        let client = StdStream::connect(addr).unwrap();
+        let mut spawned = false;
+        let acc = machine(acc.ready(EventSet::readable(), &mut lp.scope(1))
+            .map(|m| m, |seed| {
+                spawned = true;
+                let conn = machine(Spawn::<Listener>::create(seed,
+                    &mut lp.scope(2)));
+                match conn {
+                    Spawn::Child(Connection(peer)) => {
+                        assert_eq!(peer, client.local_addr().unwrap());
+                    }
+                    _ => panic!("connection must be accepted"),
+                }
+            }));
+        assert!(spawned);
+
+        lp.ctx().shutdown.request();
+        assert!(acc.wakeup(&mut lp.scope(1)).is_stopped());
+        assert_eq!(lp.ctx().shutdown.live(), 0);
+    }
+}
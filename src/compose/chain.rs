@@ -0,0 +1,295 @@
+use rotor::mio::EventSet;
+use rotor::void::{Void, unreachable};
+use rotor::{Machine, Scope, Response, SpawnError};
+
+use super::Either;
+
+
+/// A state machine that is a single phase of a multi-phase protocol
+pub trait Phase: Machine {
+    /// The value this phase finishes with, i.e. the seed of the next phase
+    type Output;
+
+    /// Checks whether the phase is finished
+    ///
+    /// Called by `Chain` after every event handler of the machine that
+    /// returned a machine. Returns either an output to switch to the
+    /// next phase, or the machine itself to continue this phase.
+    fn finish(self, scope: &mut Scope<Self::Context>)
+        -> Result<Self::Output, Self>;
+}
+
+/// Runs machine `A`, and then machine `B` in the same slot
+///
+/// When `A` finishes (see `Phase::finish`), `B` is created with its output
+/// as a seed. The token of the state machine is preserved, so notifiers
+/// created by `A` wake up `B`, and sockets may be passed in the seed
+/// without re-registering. Chains of more than two phases are built by
+/// nesting: `Chain<Chain<A, B>, C>`.
+///
+/// The seed of the chain is an `Either`, `Left` creates the first phase
+/// and `Right` the second one.
+pub enum Chain<A, B: Machine> {
+    First(A),
+    Second(B),
+    /// The first phase is finished and has spawned a seed at the same
+    /// time, `B` is created on `spawned()` event which follows
+    Next(B::Seed),
+}
+
+impl<A, B, X, S> Chain<A, B>
+    where A: Phase<Context=X, Output=S>,
+          B: Machine<Context=X, Seed=S>,
+{
+    fn first(response: Response<A, A::Seed>, scope: &mut Scope<X>)
+        -> Response<Self, Either<A::Seed, B::Seed>>
+    {
+        let mut spawned = false;
+        let mut finished = false;
+        let response = response.map(|m| match m.finish(scope) {
+            Ok(seed) => {
+                finished = true;
+                Chain::Next(seed)
+            }
+            Err(m) => Chain::First(m),
+        }, |seed| {
+            spawned = true;
+            Either::Left(seed)
+        });
+        if !finished || spawned {
+            return response;
+        }
+        let mut next = None;
+        response.wrap(|m| if let Chain::Next(seed) = m {
+            next = Some(seed);
+        });
+        Chain::create_second(next.expect("next seed"), scope)
+    }
+    fn create_second(seed: B::Seed, scope: &mut Scope<X>)
+        -> Response<Self, Either<A::Seed, B::Seed>>
+    {
+        B::create(seed, scope).map(Chain::Second, |x| unreachable(x))
+    }
+}
+
+impl<A, B, X, S> Phase for Chain<A, B>
+    where A: Phase<Context=X, Output=S>,
+          B: Phase<Context=X, Seed=S>,
+{
+    type Output = B::Output;
+
+    fn finish(self, scope: &mut Scope<X>)
+        -> Result<B::Output, Self>
+    {
+        match self {
+            Chain::Second(m) => m.finish(scope).map_err(Chain::Second),
+            me => Err(me),
+        }
+    }
+}
+
+impl<A, B, X, S> Machine for Chain<A, B>
+    where A: Phase<Context=X, Output=S>,
+          B: Machine<Context=X, Seed=S>,
+{
+    type Context = X;
+    type Seed = Either<A::Seed, B::Seed>;
+
+    fn create(seed: Self::Seed, scope: &mut Scope<X>)
+        -> Response<Self, Void>
+    {
+        match seed {
+            Either::Left(s) => A::create(s, scope).wrap(Chain::First),
+            Either::Right(s) => B::create(s, scope).wrap(Chain::Second),
+        }
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Chain::*;
+        match self {
+            First(m) => { Chain::first(m.ready(events, scope), scope) }
+            Second(m) => { m.ready(events, scope).map(Second, Either::Right) }
+            Next(_) => unreachable!(),
+        }
+    }
+    fn spawned(self, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Chain::*;
+        match self {
+            First(m) => { Chain::first(m.spawned(scope), scope) }
+            Second(m) => { m.spawned(scope).map(Second, Either::Right) }
+            Next(seed) => { Chain::create_second(seed, scope) }
+        }
+    }
+    fn timeout(self, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Chain::*;
+        match self {
+            First(m) => { Chain::first(m.timeout(scope), scope) }
+            Second(m) => { m.timeout(scope).map(Second, Either::Right) }
+            Next(_) => unreachable!(),
+        }
+    }
+    fn wakeup(self, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Chain::*;
+        match self {
+            First(m) => { Chain::first(m.wakeup(scope), scope) }
+            Second(m) => { m.wakeup(scope).map(Second, Either::Right) }
+            Next(_) => unreachable!(),
+        }
+    }
+    fn spawn_error(self, scope: &mut Scope<X>,
+                   error: SpawnError<Self::Seed>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Chain::*;
+        // the seed always belongs to the phase that has spawned it
+        match self {
+            First(m) => {
+                Chain::first(m.spawn_error(scope, error.map(|s| match s {
+                    Either::Left(s) => s,
+                    Either::Right(_) => unreachable!(),
+                })), scope)
+            }
+            Second(m) => {
+                m.spawn_error(scope, error.map(|s| match s {
+                    Either::Right(s) => s,
+                    Either::Left(_) => unreachable!(),
+                })).map(Second, Either::Right)
+            }
+            // the first phase is finished anyway
+            Next(seed) => { Chain::create_second(seed, scope) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::Void;
+
+    use compose::Either;
+    use super::{Chain, Phase};
+    use util::test::machine;
+
+    /// Finishes after two events with the number of events, spawns a
+    /// seed on each wakeup if `true`
+    struct Handshake(u32, bool);
+
+    /// Stores its seed
+    struct Serve(u32);
+
+    impl Machine for Handshake {
+        type Context = ();
+        type Seed = bool;
+        fn create(seed: bool, _scope: &mut Scope<()>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Handshake(0, seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, bool>
+        {
+            Response::ok(Handshake(self.0 + 1, self.1))
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, bool> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, bool> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, bool> {
+            let me = Handshake(self.0 + 1, self.1);
+            if me.1 {
+                Response::spawn(me, false)
+            } else {
+                Response::ok(me)
+            }
+        }
+    }
+
+    impl Phase for Handshake {
+        type Output = u32;
+        fn finish(self, _scope: &mut Scope<()>) -> Result<u32, Self> {
+            if self.0 >= 2 {
+                Ok(self.0)
+            } else {
+                Err(self)
+            }
+        }
+    }
+
+    impl Machine for Serve {
+        type Context = ();
+        type Seed = u32;
+        fn create(seed: u32, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Serve(seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, u32>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            Response::done()
+        }
+    }
+
+    type Fsm = Chain<Handshake, Serve>;
+
+    #[test]
+    fn test_chain() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = machine(Fsm::create(Either::Left(false), &mut lp.scope(1)));
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        let fsm = machine(fsm.ready(EventSet::readable(), &mut lp.scope(1)));
+        match fsm {
+            Chain::Second(Serve(2)) => {}
+            _ => panic!("must switch to the second phase"),
+        }
+        assert!(fsm.wakeup(&mut lp.scope(1)).is_stopped());
+    }
+
+    #[test]
+    fn test_spawn_and_finish() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = machine(Fsm::create(Either::Left(true), &mut lp.scope(1)));
+        let mut seeds = 0;
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1))
+            .map(|m| m, |s| match s {
+                Either::Left(false) => seeds += 1,
+                _ => panic!("seed of the first phase expected"),
+            }));
+        match fsm {
+            Chain::First(Handshake(1, true)) => {}
+            _ => panic!("still in the first phase"),
+        }
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1))
+            .map(|m| m, |s| match s {
+                Either::Left(false) => seeds += 1,
+                _ => panic!("seed of the first phase expected"),
+            }));
+        assert_eq!(seeds, 2);
+        match fsm {
+            Chain::Next(2) => {}
+            _ => panic!("switches on spawned()"),
+        }
+        match machine(fsm.spawned(&mut lp.scope(1))) {
+            Chain::Second(Serve(2)) => {}
+            _ => panic!("must switch to the second phase"),
+        }
+    }
+}
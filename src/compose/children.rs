@@ -0,0 +1,110 @@
+use rotor::Notifier;
+
+use super::Rejection;
+
+
+/// A counter of live children of the `Spawn` state machine
+///
+/// Put it into the context and return it from `Spawner::children` to limit
+/// the number of children. When the limit is reached, the spawner is
+/// paused with `Spawner::pause` instead of receiving `spawned()` event, and
+/// it's resumed with `Spawner::resume` as soon as any child finishes.
+///
+/// Seeds which are spawned while the limit is reached are dropped (i.e.
+/// the connection is closed), and `Spawn::Rejected` placeholder is created
+/// instead of the child.
+///
+/// It also counts accepted and rejected seeds, and passes the reason of
+/// rejection to `Spawner::rejected`. Use `Children::unlimited()` if you
+/// need only these.
+#[derive(Debug)]
+pub struct Children {
+    live: usize,
+    limit: usize,
+    paused: Option<Notifier>,
+    accepted: u64,
+    rejected: u64,
+    rejection: Option<Rejection>,
+}
+
+impl Children {
+    pub fn new(limit: usize) -> Children {
+        Children {
+            live: 0,
+            limit,
+            paused: None,
+            accepted: 0,
+            rejected: 0,
+            rejection: None,
+        }
+    }
+    /// A counter without a limit, useful for statistics and rejections
+    pub fn unlimited() -> Children {
+        Children::new(usize::MAX)
+    }
+    /// Number of children that are currently running
+    pub fn live(&self) -> usize {
+        self.live
+    }
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+    /// Returns true if the spawner waits for some child to finish
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+    pub fn is_full(&self) -> bool {
+        self.live >= self.limit
+    }
+    /// Total number of seeds that successfully created a child
+    pub fn accepted(&self) -> u64 {
+        self.accepted
+    }
+    /// Total number of seeds that were rejected for any reason
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+    /// Registers a child if limit is not reached yet
+    pub(crate) fn admit(&mut self) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.live += 1;
+        true
+    }
+    /// Counts a child that is created successfully
+    pub(crate) fn started(&mut self) {
+        self.accepted += 1;
+    }
+    /// Counts a rejected seed and stores the reason for the spawner
+    pub(crate) fn reject(&mut self, reason: Rejection) {
+        self.rejected += 1;
+        self.rejection = Some(reason);
+    }
+    /// Returns the reason of the seed rejected since last `spawned()`
+    pub(crate) fn take_rejection(&mut self) -> Option<Rejection> {
+        self.rejection.take()
+    }
+    /// Marks the spawner as paused, `notifier` is used to wake it up
+    pub(crate) fn pause(&mut self, notifier: Notifier) {
+        self.paused = Some(notifier);
+    }
+    /// Unregisters a finished child and wakes up the spawner if needed
+    pub(crate) fn finished(&mut self) {
+        self.live -= 1;
+        if let Some(ref notifier) = self.paused {
+            // fails only if the loop is shutting down or notification
+            // queue is full, there is nothing we can do in either case
+            notifier.wakeup().ok();
+        }
+    }
+    /// Returns true if the paused spawner should be resumed now
+    pub(crate) fn take_resume(&mut self) -> bool {
+        if self.paused.is_some() && !self.is_full() {
+            self.paused = None;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use rotor::mio::EventSet;
+use rotor::void::Void;
+use rotor::{Machine, Scope, Response, SpawnError};
+
+
+/// One of two state machines sharing the same context
+///
+/// This is a generic version of the enum produced by `rotor_compose!`,
+/// useful in generic library code where the types of the machines are not
+/// known. The seed of the machine is an `Either` of the seeds too.
+#[derive(Debug)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// One of three state machines sharing the same context
+///
+/// Same as `Either` but for three machines.
+#[derive(Debug)]
+pub enum Either3<A, B, C> {
+    First(A),
+    Second(B),
+    Third(C),
+}
+
+impl<A, B, X> Machine for Either<A, B>
+    where A: Machine<Context=X>,
+          B: Machine<Context=X>,
+{
+    type Context = X;
+    type Seed = Either<A::Seed, B::Seed>;
+
+    fn create(seed: Self::Seed, scope: &mut Scope<X>)
+        -> Response<Self, Void>
+    {
+        use self::Either::*;
+        match seed {
+            Left(s) => A::create(s, scope).wrap(Left),
+            Right(s) => B::create(s, scope).wrap(Right),
+        }
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Either::*;
+        match self {
+            Left(m) => m.ready(events, scope).map(Left, Left),
+            Right(m) => m.ready(events, scope).map(Right, Right),
+        }
+    }
+    fn spawned(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either::*;
+        match self {
+            Left(m) => m.spawned(scope).map(Left, Left),
+            Right(m) => m.spawned(scope).map(Right, Right),
+        }
+    }
+    fn timeout(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either::*;
+        match self {
+            Left(m) => m.timeout(scope).map(Left, Left),
+            Right(m) => m.timeout(scope).map(Right, Right),
+        }
+    }
+    fn wakeup(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either::*;
+        match self {
+            Left(m) => m.wakeup(scope).map(Left, Left),
+            Right(m) => m.wakeup(scope).map(Right, Right),
+        }
+    }
+    fn spawn_error(self, scope: &mut Scope<X>,
+                   error: SpawnError<Self::Seed>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Either::*;
+        // the seed always belongs to the machine that has spawned it
+        match self {
+            Left(m) => m.spawn_error(scope, error.map(|s| match s {
+                Left(s) => s,
+                Right(_) => unreachable!(),
+            })).map(Left, Left),
+            Right(m) => m.spawn_error(scope, error.map(|s| match s {
+                Right(s) => s,
+                Left(_) => unreachable!(),
+            })).map(Right, Right),
+        }
+    }
+}
+
+impl<A, B, C, X> Machine for Either3<A, B, C>
+    where A: Machine<Context=X>,
+          B: Machine<Context=X>,
+          C: Machine<Context=X>,
+{
+    type Context = X;
+    type Seed = Either3<A::Seed, B::Seed, C::Seed>;
+
+    fn create(seed: Self::Seed, scope: &mut Scope<X>)
+        -> Response<Self, Void>
+    {
+        use self::Either3::*;
+        match seed {
+            First(s) => A::create(s, scope).wrap(First),
+            Second(s) => B::create(s, scope).wrap(Second),
+            Third(s) => C::create(s, scope).wrap(Third),
+        }
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<X>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Either3::*;
+        match self {
+            First(m) => m.ready(events, scope).map(First, First),
+            Second(m) => m.ready(events, scope).map(Second, Second),
+            Third(m) => m.ready(events, scope).map(Third, Third),
+        }
+    }
+    fn spawned(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either3::*;
+        match self {
+            First(m) => m.spawned(scope).map(First, First),
+            Second(m) => m.spawned(scope).map(Second, Second),
+            Third(m) => m.spawned(scope).map(Third, Third),
+        }
+    }
+    fn timeout(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either3::*;
+        match self {
+            First(m) => m.timeout(scope).map(First, First),
+            Second(m) => m.timeout(scope).map(Second, Second),
+            Third(m) => m.timeout(scope).map(Third, Third),
+        }
+    }
+    fn wakeup(self, scope: &mut Scope<X>) -> Response<Self, Self::Seed> {
+        use self::Either3::*;
+        match self {
+            First(m) => m.wakeup(scope).map(First, First),
+            Second(m) => m.wakeup(scope).map(Second, Second),
+            Third(m) => m.wakeup(scope).map(Third, Third),
+        }
+    }
+    fn spawn_error(self, scope: &mut Scope<X>,
+                   error: SpawnError<Self::Seed>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Either3::*;
+        // the seed always belongs to the machine that has spawned it
+        match self {
+            First(m) => m.spawn_error(scope, error.map(|s| match s {
+                First(s) => s,
+                _ => unreachable!(),
+            })).map(First, First),
+            Second(m) => m.spawn_error(scope, error.map(|s| match s {
+                Second(s) => s,
+                _ => unreachable!(),
+            })).map(Second, Second),
+            Third(m) => m.spawn_error(scope, error.map(|s| match s {
+                Third(s) => s,
+                _ => unreachable!(),
+            })).map(Third, Third),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::Void;
+
+    use super::Either;
+    use util::test::machine;
+
+    /// Counts events, spawns its value on every wakeup
+    struct Counter(usize);
+
+    /// Stops on any event
+    struct Oneshot;
+
+    impl Machine for Counter {
+        type Context = ();
+        type Seed = usize;
+        fn create(seed: usize, _scope: &mut Scope<()>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Counter(seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, usize>
+        {
+            Response::ok(Counter(self.0 + 1))
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, usize> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, usize> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, usize> {
+            let seed = self.0;
+            Response::spawn(self, seed)
+        }
+    }
+
+    impl Machine for Oneshot {
+        type Context = ();
+        type Seed = ();
+        fn create(_seed: (), _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Oneshot)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, ()>
+        {
+            Response::done()
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, ()> {
+            Response::done()
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, ()> {
+            Response::done()
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, ()> {
+            Response::done()
+        }
+    }
+
+    type Fsm = Either<Counter, Oneshot>;
+
+    #[test]
+    fn test_either() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = machine(Fsm::create(Either::Left(1), &mut lp.scope(1)));
+        let fsm = machine(fsm.ready(EventSet::readable(), &mut lp.scope(1)));
+        let mut seed = None;
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1))
+            .map(|m| m, |s| seed = Some(s)));
+        match (fsm, seed) {
+            (Either::Left(Counter(2)), Some(Either::Left(2))) => {}
+            _ => panic!("counter must spawn its value"),
+        }
+        let fsm = machine(Fsm::create(Either::Right(()), &mut lp.scope(2)));
+        assert!(fsm.timeout(&mut lp.scope(2)).is_stopped());
+    }
+}
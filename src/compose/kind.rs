@@ -0,0 +1,47 @@
+use std::fmt;
+
+use rotor::Response;
+
+
+/// The kind of the `Response` returned by a state machine
+///
+/// Note: rotor doesn't expose the deadline of the response, so responses
+/// with a deadline are reported as `Ok` too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    Ok,
+    Spawn,
+    Done,
+    Error,
+}
+
+impl ResponseKind {
+    /// Returns the kind of the response and the response itself
+    pub fn of<M, N>(response: Response<M, N>) -> (ResponseKind, Response<M, N>)
+    {
+        if response.is_stopped() {
+            if response.cause().is_some() {
+                return (ResponseKind::Error, response);
+            }
+            return (ResponseKind::Done, response);
+        }
+        let mut kind = ResponseKind::Ok;
+        let response = response.map(|m| m, |seed| {
+            kind = ResponseKind::Spawn;
+            seed
+        });
+        (kind, response)
+    }
+}
+
+impl fmt::Display for ResponseKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::ResponseKind::*;
+        fmt.write_str(match *self {
+            Ok => "ok",
+            Spawn => "spawn",
+            Done => "done",
+            Error => "error",
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+use rotor::{Notifier, WakeupError};
+
+use future::GetNotifier;
+use mailbox::SendError;
+use util::lock;
+
+
+struct State<M, R> {
+    message: Option<M>,
+    report: Option<R>,
+    child: Option<Notifier>,
+    finished: bool,
+}
+
+struct Shared<M, R> {
+    state: Mutex<State<M, R>>,
+    parent: Notifier,
+}
+
+/// The child's side of the link between the spawner and the child
+///
+/// It's created by the spawner and passed to the child in the seed. The
+/// child should `attach()` it in `Spawner::spawn` and keep it till the end,
+/// since dropping the link (i.e. returning `Response::done()` from the
+/// child) is what notifies the parent about completion:
+///
+/// ```ignore
+/// // in the parent
+/// let (handle, link) = Link::new(&mut *scope);
+/// self.children.push(handle);
+/// Response::spawn(self, (sock, link))
+///
+/// // in Spawner::spawn
+/// link.attach(&mut *scope);
+/// Response::ok(Connection { sock, link })
+/// ```
+pub struct Link<M, R=()>(Arc<Shared<M, R>>);
+
+/// The parent's side of the link, used to send messages to the child
+pub struct ChildHandle<M, R=()>(Arc<Shared<M, R>>);
+
+impl<M, R> Link<M, R> {
+    /// Creates a link, the `parent` is woken up when the child finishes
+    /// or sends a report
+    pub fn new<N: GetNotifier>(parent: N) -> (ChildHandle<M, R>, Link<M, R>) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                message: None,
+                report: None,
+                child: None,
+                finished: false,
+            }),
+            parent: parent.get_notifier(),
+        });
+        (ChildHandle(shared.clone()), Link(shared))
+    }
+    /// Sets the state machine which is woken up on messages from parent
+    ///
+    /// If a message was sent before the child was attached, the child is
+    /// woken up immediately.
+    pub fn attach<N: GetNotifier>(&self, child: N) {
+        let notifier = child.get_notifier();
+        let mut state = lock(&self.0.state);
+        if state.message.is_some() {
+            notifier.wakeup().ok();
+        }
+        state.child = Some(notifier);
+    }
+    /// Takes the message sent by the parent, if any
+    pub fn recv(&self) -> Option<M> {
+        lock(&self.0.state).message.take()
+    }
+    /// Stores the report for the parent and wakes it up
+    ///
+    /// Only the latest report is kept if parent hasn't read previous one.
+    pub fn report(&self, value: R) {
+        lock(&self.0.state).report = Some(value);
+        self.0.parent.wakeup().ok();
+    }
+}
+
+impl<M, R> ChildHandle<M, R> {
+    /// Puts the message into the slot and wakes up the child
+    ///
+    /// Returns `SendError::Full` if previous message isn't received by the
+    /// child yet, and `SendError::Closed` if the child has finished.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        let mut state = lock(&self.0.state);
+        if state.finished {
+            return Err(SendError::Closed(message));
+        }
+        if state.message.is_some() {
+            return Err(SendError::Full(message));
+        }
+        if let Some(ref notifier) = state.child {
+            match notifier.wakeup() {
+                Ok(()) => {}
+                Err(WakeupError::Full) => return Err(SendError::Full(message)),
+                Err(_) => {
+                    state.finished = true;
+                    return Err(SendError::Closed(message));
+                }
+            }
+        }
+        state.message = Some(message);
+        Ok(())
+    }
+    /// The notifier of the child, if it's already attached
+    pub fn notifier(&self) -> Option<Notifier> {
+        lock(&self.0.state).child.clone()
+    }
+    /// Takes the latest report sent by the child
+    pub fn take_report(&self) -> Option<R> {
+        lock(&self.0.state).report.take()
+    }
+    /// Returns true if the child has finished (the `Link` is dropped)
+    pub fn is_finished(&self) -> bool {
+        lock(&self.0.state).finished
+    }
+}
+
+impl<M, R> Drop for Link<M, R> {
+    fn drop(&mut self) {
+        let mut state = lock(&self.0.state);
+        state.finished = true;
+        state.message = None;
+        self.0.parent.wakeup().ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use mailbox::SendError;
+    use super::Link;
+
+    #[test]
+    fn test_link() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let (handle, link) = Link::<&str, usize>::new(&mut lp.scope(1));
+        assert!(handle.notifier().is_none());
+        handle.send("hello").unwrap();
+        match handle.send("again") {
+            Err(SendError::Full("again")) => {}
+            _ => panic!("slot must be full"),
+        }
+        link.attach(&mut lp.scope(2));
+        assert!(handle.notifier().is_some());
+        assert_eq!(link.recv(), Some("hello"));
+        assert_eq!(link.recv(), None);
+
+        link.report(10);
+        assert_eq!(handle.take_report(), Some(10));
+        assert_eq!(handle.take_report(), None);
+        assert!(!handle.is_finished());
+
+        drop(link);
+        assert!(handle.is_finished());
+        match handle.send("close") {
+            Err(SendError::Closed("close")) => {}
+            _ => panic!("link must be closed"),
+        }
+    }
+}
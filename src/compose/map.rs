@@ -0,0 +1,125 @@
+use std::marker::PhantomData;
+
+use rotor::mio::EventSet;
+use rotor::void::Void;
+use rotor::{Machine, Scope, Response, SpawnError};
+
+
+/// A state machine with the seed converted to another type
+///
+/// The `S` is the seed of the adapter. It's converted into the seed of the
+/// machine `M` in `create()`, and seeds spawned by `M` are converted back,
+/// so both `S: Into<M::Seed>` and `M::Seed: Into<S>` are required.
+///
+/// Note: there is no counterpart for the context, because `Scope` can't be
+/// constructed for a part of the context outside of rotor itself. Make the
+/// machine generic over a context implementing some trait instead.
+pub struct MapSeed<M, S>(M, PhantomData<fn(S) -> S>);
+
+impl<M, S> MapSeed<M, S> {
+    pub fn new(machine: M) -> MapSeed<M, S> {
+        MapSeed(machine, PhantomData)
+    }
+    pub fn get_ref(&self) -> &M {
+        &self.0
+    }
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M, S> Machine for MapSeed<M, S>
+    where M: Machine,
+          S: Into<M::Seed>,
+          M::Seed: Into<S>,
+{
+    type Context = M::Context;
+    type Seed = S;
+
+    fn create(seed: S, scope: &mut Scope<M::Context>)
+        -> Response<Self, Void>
+    {
+        M::create(seed.into(), scope).wrap(MapSeed::new)
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<M::Context>)
+        -> Response<Self, S>
+    {
+        self.0.ready(events, scope).map(MapSeed::new, Into::into)
+    }
+    fn spawned(self, scope: &mut Scope<M::Context>) -> Response<Self, S> {
+        self.0.spawned(scope).map(MapSeed::new, Into::into)
+    }
+    fn timeout(self, scope: &mut Scope<M::Context>) -> Response<Self, S> {
+        self.0.timeout(scope).map(MapSeed::new, Into::into)
+    }
+    fn wakeup(self, scope: &mut Scope<M::Context>) -> Response<Self, S> {
+        self.0.wakeup(scope).map(MapSeed::new, Into::into)
+    }
+    fn spawn_error(self, scope: &mut Scope<M::Context>,
+                   error: SpawnError<S>)
+        -> Response<Self, S>
+    {
+        self.0.spawn_error(scope, error.map(Into::into))
+            .map(MapSeed::new, Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::Void;
+
+    use super::MapSeed;
+
+    /// Spawns a copy of itself on every wakeup
+    struct Counter(u32);
+
+    #[derive(Debug, PartialEq)]
+    struct Seed(u32);
+
+    impl From<Seed> for u32 {
+        fn from(seed: Seed) -> u32 { seed.0 }
+    }
+
+    impl From<u32> for Seed {
+        fn from(value: u32) -> Seed { Seed(value) }
+    }
+
+    impl Machine for Counter {
+        type Context = ();
+        type Seed = u32;
+        fn create(seed: u32, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Counter(seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, u32>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            let seed = self.0;
+            Response::spawn(self, seed)
+        }
+    }
+
+    #[test]
+    fn test_map_seed() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let mut fsm = None;
+        MapSeed::<Counter, Seed>::create(Seed(7), &mut lp.scope(1))
+            .wrap(|m| fsm = Some(m));
+        let fsm = fsm.expect("machine is created");
+        assert_eq!(fsm.get_ref().0, 7);
+        let mut seed = None;
+        fsm.wakeup(&mut lp.scope(1)).map(|m| m, |s| seed = Some(s));
+        assert_eq!(seed, Some(Seed(7)));
+    }
+}
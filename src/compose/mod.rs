@@ -0,0 +1,556 @@
+//! Composition tools
+
+use std::fmt;
+use std::error::Error;
+
+use rotor::mio::EventSet;
+use rotor::void::{Void, unreachable};
+use rotor::{Machine, Scope, Response, SpawnError};
+
+use shutdown::Shutdown;
+
+mod children;
+mod accept;
+mod link;
+mod either;
+mod map;
+mod chain;
+mod kind;
+#[cfg(feature="log")] mod traced;
+
+pub use self::children::Children;
+pub use self::accept::{Accept, Accepted};
+pub use self::link::{Link, ChildHandle};
+pub use self::either::{Either, Either3};
+pub use self::map::MapSeed;
+pub use self::chain::{Chain, Phase};
+pub use self::kind::ResponseKind;
+#[cfg(feature="log")] pub use self::traced::Traced;
+
+
+/// Composes two state machines where of the state machines spawns
+/// multiple instances of another one
+///
+/// Use `Link` in the seed if the spawner needs to communicate with its
+/// children after they are spawned.
+pub enum Spawn<S: Spawner> {
+    Spawner(S),
+    /// A child which is not counted in `Children`
+    ///
+    /// It's either created directly, or `Spawner::children` returns `None`.
+    Child(S::Child),
+    /// A child admitted by `Machine::create` and counted in `Children`
+    Admitted(S::Child),
+    /// A placeholder for the seed that wasn't spawned
+    ///
+    /// Rotor doesn't allow `Machine::create` to fail, so this state is
+    /// created instead, and it stops on the first (immediate) timeout.
+    Rejected,
+}
+
+/// The reason why the seed was not spawned
+#[derive(Debug)]
+pub enum Rejection {
+    /// Shutdown was requested
+    Shutdown,
+    /// The limit of children is reached
+    Limit,
+    /// The seed was refused by `Spawner::filter`
+    Refused(Box<dyn Error>),
+    /// The `Spawner::spawn` returned a stopped state machine
+    Stopped,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::Rejection::*;
+        match *self {
+            Shutdown => write!(fmt, "shutdown requested"),
+            Limit => write!(fmt, "limit of children reached"),
+            Refused(ref e) => write!(fmt, "seed refused: {}", e),
+            Stopped => write!(fmt, "child stopped on creation"),
+        }
+    }
+}
+
+pub trait Spawner {
+    type Child: Machine<Seed=Void>;
+    type Seed;
+
+    fn spawn(seed: Self::Seed,
+        scope: &mut Scope<<Self::Child as Machine>::Context>)
+        -> Response<Self::Child, Void>;
+
+    /// Checks the seed before spawning a child
+    ///
+    /// Returning an error rejects the seed with `Rejection::Refused`, the
+    /// seed is dropped. Default implementation accepts every seed.
+    fn filter(_seed: &Self::Seed,
+        _scope: &mut Scope<<Self::Child as Machine>::Context>)
+        -> Result<(), Box<dyn Error>>
+    {
+        Ok(())
+    }
+
+    /// Returns the counter used to limit the number of children
+    ///
+    /// Default implementation returns `None`, i.e. there is no limit.
+    fn children(_context: &mut <Self::Child as Machine>::Context)
+        -> Option<&mut Children>
+    {
+        None
+    }
+    /// Returns the shutdown token, if spawning should stop on shutdown
+    ///
+    /// After the shutdown is requested all new seeds are rejected. Note
+    /// that both the spawner and the children should register themselves
+    /// in the `Shutdown` to be woken up when shutdown is requested.
+    fn shutdown(_context: &mut <Self::Child as Machine>::Context)
+        -> Option<&Shutdown>
+    {
+        None
+    }
+    /// Called instead of `spawned()` when the limit of children is reached
+    ///
+    /// The spawner should stop accepting new seeds (i.e. deregister the
+    /// listening socket) until `resume()` is called.
+    fn pause(self, _scope: &mut Scope<<Self::Child as Machine>::Context>)
+        -> Response<Self, Self::Seed>
+        where Self: Sized
+    {
+        Response::ok(self)
+    }
+    /// Called instead of `wakeup()` when some child has finished after
+    /// the spawner was paused
+    fn resume(self, _scope: &mut Scope<<Self::Child as Machine>::Context>)
+        -> Response<Self, Self::Seed>
+        where Self: Sized
+    {
+        Response::ok(self)
+    }
+    /// Called instead of `spawned()` when the seed was rejected
+    ///
+    /// Rejections are passed through the `Children` counter, so this is
+    /// called only if `children()` returns it.
+    fn rejected(self, _reason: Rejection,
+        _scope: &mut Scope<<Self::Child as Machine>::Context>)
+        -> Response<Self, Self::Seed>
+        where Self: Sized
+    {
+        Response::ok(self)
+    }
+}
+
+impl<T, C, S> Spawner for ::uniform::Uniform<T>
+    where T: Spawner<Seed=S> + ::uniform::Action<Seed=S, Context=C>
+{
+    type Child = T::Child;
+    type Seed = <T as Spawner>::Seed;
+
+    fn spawn(seed: <Self as Spawner>::Seed,
+        scope: &mut Scope<<<Self as Spawner>::Child as Machine>::Context>)
+        -> Response<Self::Child, Void>
+    {
+        T::spawn(seed, scope)
+    }
+    fn filter(seed: &<Self as Spawner>::Seed,
+        scope: &mut Scope<<T::Child as Machine>::Context>)
+        -> Result<(), Box<dyn Error>>
+    {
+        T::filter(seed, scope)
+    }
+    fn children(context: &mut <T::Child as Machine>::Context)
+        -> Option<&mut Children>
+    {
+        T::children(context)
+    }
+    fn shutdown(context: &mut <T::Child as Machine>::Context)
+        -> Option<&Shutdown>
+    {
+        T::shutdown(context)
+    }
+    fn pause(self, scope: &mut Scope<<T::Child as Machine>::Context>)
+        -> Response<Self, <Self as Spawner>::Seed>
+    {
+        Spawner::pause(self.0, scope).wrap(::uniform::Uniform)
+    }
+    fn resume(self, scope: &mut Scope<<T::Child as Machine>::Context>)
+        -> Response<Self, <Self as Spawner>::Seed>
+    {
+        Spawner::resume(self.0, scope).wrap(::uniform::Uniform)
+    }
+    fn rejected(self, reason: Rejection,
+        scope: &mut Scope<<T::Child as Machine>::Context>)
+        -> Response<Self, <Self as Spawner>::Seed>
+    {
+        Spawner::rejected(self.0, reason, scope).wrap(::uniform::Uniform)
+    }
+}
+
+fn reject<S, C>(reason: Rejection, scope: &mut Scope<C::Context>)
+    -> Response<Spawn<S>, Void>
+    where S: Spawner<Child=C>,
+          C: Machine<Seed=Void>,
+{
+    if let Some(children) = S::children(&mut **scope) {
+        children.reject(reason);
+    }
+    Response::ok(Spawn::Rejected).deadline(scope.now())
+}
+
+fn child<S, C>(response: Response<C, Void>, admitted: bool,
+    scope: &mut Scope<C::Context>)
+    -> Response<Spawn<S>, S::Seed>
+    where S: Spawner<Child=C>,
+          C: Machine<Seed=Void>,
+{
+    if !admitted {
+        return response.map(Spawn::Child, |x| unreachable(x));
+    }
+    if response.is_stopped() {
+        if let Some(children) = S::children(&mut **scope) {
+            children.finished();
+        }
+    }
+    response.map(Spawn::Admitted, |x| unreachable(x))
+}
+
+impl<S, C, D> Machine for Spawn<S>
+    where S: Spawner<Child=C, Seed=D> + Machine<Context=C::Context, Seed=D>,
+          C: Machine<Seed=Void>,
+{
+    type Context = <S::Child as Machine>::Context;
+    type Seed = <S as Spawner>::Seed;
+
+    fn create(seed: <S as Spawner>::Seed, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Void>
+    {
+        let shutdown = match S::shutdown(&mut **scope) {
+            Some(shutdown) => shutdown.is_requested(),
+            None => false,
+        };
+        if shutdown {
+            return reject::<S, C>(Rejection::Shutdown, scope);
+        }
+        if let Err(e) = S::filter(&seed, scope) {
+            return reject::<S, C>(Rejection::Refused(e), scope);
+        }
+        let admitted = match S::children(&mut **scope) {
+            Some(children) => {
+                if !children.admit() {
+                    return reject::<S, C>(Rejection::Limit, scope);
+                }
+                true
+            }
+            None => false,
+        };
+        let response = S::spawn(seed, scope);
+        if let Some(children) = S::children(&mut **scope) {
+            if response.is_stopped() {
+                children.finished();
+                children.reject(Rejection::Stopped);
+            } else {
+                children.started();
+            }
+        }
+        if response.is_stopped() {
+            return Response::ok(Spawn::Rejected).deadline(scope.now());
+        }
+        if admitted {
+            response.wrap(Spawn::Admitted)
+        } else {
+            response.wrap(Spawn::Child)
+        }
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Spawn::*;
+        match self {
+            Spawner(m) => { m.ready(events, scope).wrap(Spawner) }
+            Child(m) => { child(m.ready(events, scope), false, scope) }
+            Admitted(m) => { child(m.ready(events, scope), true, scope) }
+            Rejected => Response::done(),
+        }
+    }
+    fn spawned(self, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Spawn::*;
+        match self {
+            Spawner(m) => {
+                let notifier = scope.notifier();
+                match S::children(&mut **scope) {
+                    Some(ref mut children) => {
+                        if let Some(reason) = children.take_rejection() {
+                            return S::rejected(m, reason, scope)
+                                .wrap(Spawner);
+                        }
+                        if !children.is_full() {
+                            return m.spawned(scope).wrap(Spawner);
+                        }
+                        children.pause(notifier);
+                    }
+                    None => return m.spawned(scope).wrap(Spawner),
+                }
+                S::pause(m, scope).wrap(Spawner)
+            }
+            Child(m) => { child(m.spawned(scope), false, scope) }
+            Admitted(m) => { child(m.spawned(scope), true, scope) }
+            Rejected => Response::done(),
+        }
+    }
+    fn timeout(self, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Spawn::*;
+        match self {
+            Spawner(m) => { m.timeout(scope).wrap(Spawner) }
+            Child(m) => { child(m.timeout(scope), false, scope) }
+            Admitted(m) => { child(m.timeout(scope), true, scope) }
+            Rejected => Response::done(),
+        }
+    }
+    fn wakeup(self, scope: &mut Scope<Self::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Spawn::*;
+        match self {
+            Spawner(m) => {
+                let resume = match S::children(&mut **scope) {
+                    Some(children) => children.take_resume(),
+                    None => false,
+                };
+                if resume {
+                    S::resume(m, scope).wrap(Spawner)
+                } else {
+                    m.wakeup(scope).wrap(Spawner)
+                }
+            }
+            Child(m) => { child(m.wakeup(scope), false, scope) }
+            Admitted(m) => { child(m.wakeup(scope), true, scope) }
+            Rejected => Response::done(),
+        }
+    }
+    fn spawn_error(self, scope: &mut Scope<Self::Context>,
+                   error: SpawnError<Self::Seed>)
+        -> Response<Self, Self::Seed>
+    {
+        use self::Spawn::*;
+        match self {
+            Spawner(m) => { m.spawn_error(scope, error).wrap(Spawner) }
+            // children and placeholders never spawn anything
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use std::error::Error;
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::{Void, unreachable};
+
+    use super::{Spawn, Spawner, Children, Rejection};
+    use shutdown::Shutdown;
+    use util::test::machine;
+
+    struct Context {
+        children: Children,
+        shutdown: Shutdown,
+        banned: bool,
+    }
+
+    /// Stops on wakeup
+    struct Child;
+
+    #[derive(Debug, PartialEq)]
+    enum Listener {
+        Accepting,
+        Paused,
+        Rejected(String),
+    }
+
+    impl Machine for Child {
+        type Context = Context;
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+            Response::done()
+        }
+    }
+
+    impl Machine for Listener {
+        type Context = Context;
+        type Seed = ();
+        fn create(_seed: (), _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Context>)
+            -> Response<Self, ()>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<Context>) -> Response<Self, ()> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<Context>) -> Response<Self, ()> {
+            unreachable!();
+        }
+        fn wakeup(self, _scope: &mut Scope<Context>) -> Response<Self, ()> {
+            unreachable!();
+        }
+    }
+
+    impl Spawner for Listener {
+        type Child = Child;
+        type Seed = ();
+        fn spawn(_seed: (), _scope: &mut Scope<Context>)
+            -> Response<Child, Void>
+        {
+            Response::ok(Child)
+        }
+        fn filter(_seed: &(), scope: &mut Scope<Context>)
+            -> Result<(), Box<dyn Error>>
+        {
+            if scope.banned {
+                return Err("banned".into());
+            }
+            Ok(())
+        }
+        fn children(context: &mut Context) -> Option<&mut Children> {
+            Some(&mut context.children)
+        }
+        fn shutdown(context: &mut Context) -> Option<&Shutdown> {
+            Some(&context.shutdown)
+        }
+        fn pause(self, _scope: &mut Scope<Context>) -> Response<Self, ()> {
+            Response::ok(Listener::Paused)
+        }
+        fn resume(self, _scope: &mut Scope<Context>) -> Response<Self, ()> {
+            Response::ok(Listener::Accepting)
+        }
+        fn rejected(self, reason: Rejection, _scope: &mut Scope<Context>)
+            -> Response<Self, ()>
+        {
+            Response::ok(Listener::Rejected(reason.to_string()))
+        }
+    }
+
+    fn listener(fsm: &Spawn<Listener>) -> &Listener {
+        match *fsm {
+            Spawn::Spawner(ref l) => l,
+            _ => panic!("not a spawner"),
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut lp = rotor_test::MockLoop::new(Context {
+            children: Children::new(2),
+            shutdown: Shutdown::new(),
+            banned: false,
+        });
+        let parent = Spawn::Spawner(Listener::Accepting);
+        let first = machine(Spawn::<Listener>::create((), &mut lp.scope(2)));
+        let parent = machine(parent.spawned(&mut lp.scope(1)));
+        assert_eq!(listener(&parent), &Listener::Accepting);
+        machine(Spawn::<Listener>::create((), &mut lp.scope(3)));
+        let parent = machine(parent.spawned(&mut lp.scope(1)));
+        assert_eq!(listener(&parent), &Listener::Paused);
+        assert!(lp.ctx().children.is_paused());
+
+        match machine(Spawn::<Listener>::create((), &mut lp.scope(4))) {
+            Spawn::Rejected => {}
+            _ => panic!("seed must be rejected"),
+        }
+        assert_eq!(lp.ctx().children.live(), 2);
+        let parent = machine(parent.spawned(&mut lp.scope(1)));
+        assert_eq!(listener(&parent),
+            &Listener::Rejected("limit of children reached".into()));
+
+        assert!(first.wakeup(&mut lp.scope(2)).is_stopped());
+        assert_eq!(lp.ctx().children.live(), 1);
+        let parent = machine(parent.wakeup(&mut lp.scope(1)));
+        assert_eq!(listener(&parent), &Listener::Accepting);
+        assert!(!lp.ctx().children.is_paused());
+    }
+
+    #[test]
+    fn test_not_admitted() {
+        let mut lp = rotor_test::MockLoop::new(Context {
+            children: Children::new(2),
+            shutdown: Shutdown::new(),
+            banned: false,
+        });
+        let admitted = machine(Spawn::<Listener>::create((),
+            &mut lp.scope(2)));
+        assert_eq!(lp.ctx().children.live(), 1);
+        let child = Spawn::<Listener>::Child(Child);
+        assert!(child.wakeup(&mut lp.scope(3)).is_stopped());
+        assert_eq!(lp.ctx().children.live(), 1);
+        assert!(admitted.wakeup(&mut lp.scope(2)).is_stopped());
+        assert_eq!(lp.ctx().children.live(), 0);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut lp = rotor_test::MockLoop::new(Context {
+            children: Children::new(2),
+            shutdown: Shutdown::new(),
+            banned: false,
+        });
+        machine(Spawn::<Listener>::create((), &mut lp.scope(2)));
+        lp.ctx().shutdown.request();
+        match machine(Spawn::<Listener>::create((), &mut lp.scope(3))) {
+            Spawn::Rejected => {}
+            _ => panic!("seed must be rejected"),
+        }
+        assert_eq!(lp.ctx().children.live(), 1);
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut lp = rotor_test::MockLoop::new(Context {
+            children: Children::unlimited(),
+            shutdown: Shutdown::new(),
+            banned: false,
+        });
+        let parent = Spawn::Spawner(Listener::Accepting);
+        machine(Spawn::<Listener>::create((), &mut lp.scope(2)));
+        let parent = machine(parent.spawned(&mut lp.scope(1)));
+        assert_eq!(listener(&parent), &Listener::Accepting);
+
+        lp.ctx().banned = true;
+        match machine(Spawn::<Listener>::create((), &mut lp.scope(3))) {
+            Spawn::Rejected => {}
+            _ => panic!("seed must be rejected"),
+        }
+        let parent = machine(parent.spawned(&mut lp.scope(1)));
+        assert_eq!(listener(&parent),
+            &Listener::Rejected("seed refused: banned".into()));
+        assert_eq!(lp.ctx().children.live(), 1);
+        assert_eq!(lp.ctx().children.accepted(), 1);
+        assert_eq!(lp.ctx().children.rejected(), 1);
+    }
+}
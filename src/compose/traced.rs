@@ -0,0 +1,173 @@
+use std::fmt;
+use std::any::type_name;
+use std::time::Instant;
+
+use rotor::mio::EventSet;
+use rotor::void::Void;
+use rotor::{Machine, Scope, Response, SpawnError};
+
+use super::ResponseKind;
+
+
+/// A wrapper that logs every event of the state machine
+///
+/// Every handler call is logged at the `debug` level with the name of the
+/// handler, the events (for `ready`), time spent in the handler and the
+/// kind of the response. Only available with the `log` feature.
+///
+/// It's used in place of the machine itself, i.e. `Traced<Fsm>` instead of
+/// `Fsm` in the loop, or in the `rotor_compose!` enum.
+pub struct Traced<M>(M);
+
+impl<M> Traced<M> {
+    pub fn new(machine: M) -> Traced<M> {
+        Traced(machine)
+    }
+    pub fn get_ref(&self) -> &M {
+        &self.0
+    }
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+fn trace<M, N>(handler: fmt::Arguments, start: Instant,
+    response: Response<M, N>)
+    -> Response<Traced<M>, N>
+{
+    let elapsed = start.elapsed();
+    let (kind, response) = ResponseKind::of(response);
+    match response.cause() {
+        Some(e) => debug!("{}::{} -> {} ({}) in {:?}",
+            type_name::<M>(), handler, kind, e, elapsed),
+        None => debug!("{}::{} -> {} in {:?}",
+            type_name::<M>(), handler, kind, elapsed),
+    }
+    response.wrap(Traced)
+}
+
+impl<M: Machine> Machine for Traced<M> {
+    type Context = M::Context;
+    type Seed = M::Seed;
+
+    fn create(seed: M::Seed, scope: &mut Scope<M::Context>)
+        -> Response<Self, Void>
+    {
+        let start = Instant::now();
+        trace(format_args!("create"), start, M::create(seed, scope))
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let start = Instant::now();
+        let response = self.0.ready(events, scope);
+        trace(format_args!("ready({:?})", events), start, response)
+    }
+    fn spawned(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let start = Instant::now();
+        trace(format_args!("spawned"), start, self.0.spawned(scope))
+    }
+    fn timeout(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let start = Instant::now();
+        trace(format_args!("timeout"), start, self.0.timeout(scope))
+    }
+    fn wakeup(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let start = Instant::now();
+        trace(format_args!("wakeup"), start, self.0.wakeup(scope))
+    }
+    fn spawn_error(self, scope: &mut Scope<M::Context>,
+                   error: SpawnError<M::Seed>)
+        -> Response<Self, M::Seed>
+    {
+        let start = Instant::now();
+        let reason = error.to_string();
+        trace(format_args!("spawn_error({})", reason), start,
+            self.0.spawn_error(scope, error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use std::sync::{Arc, Mutex};
+
+    use log::{self, Log, LogRecord, LogMetadata, LogLevelFilter};
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::Void;
+
+    use super::Traced;
+    use util::test::machine;
+
+    /// Collects all logged messages (only `Traced` logs anything)
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _metadata: &LogMetadata) -> bool {
+            true
+        }
+        fn log(&self, record: &LogRecord) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    /// Spawns its value on wakeup, stops on timeout
+    struct Counter(u32);
+
+    impl Machine for Counter {
+        type Context = ();
+        type Seed = u32;
+        fn create(seed: u32, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Counter(seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, u32>
+        {
+            Response::ok(Counter(self.0 + 1))
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            Response::done()
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, u32> {
+            let seed = self.0;
+            Response::spawn(self, seed)
+        }
+    }
+
+    #[test]
+    fn test_traced() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let capture = Capture(lines.clone());
+        log::set_logger(|max_level| {
+            max_level.set(LogLevelFilter::Debug);
+            Box::new(capture)
+        }).expect("no other logger in tests");
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = machine(Traced::<Counter>::create(1, &mut lp.scope(1)));
+        let fsm = machine(fsm.ready(EventSet::readable(), &mut lp.scope(1)));
+        assert_eq!(fsm.get_ref().0, 2);
+        let mut seed = None;
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1))
+            .map(|m| m, |s| seed = Some(s)));
+        assert_eq!(seed, Some(2));
+        assert!(fsm.timeout(&mut lp.scope(1)).is_stopped());
+
+        let lines = lines.lock().unwrap();
+        let name = "rotor_tools::compose::traced::test::Counter";
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(&format!("{}::create -> ok in ", name)));
+        assert!(lines[1].starts_with(&format!(
+            "{}::ready({:?}) -> ok in ", name, EventSet::readable())));
+        assert!(lines[2].starts_with(&format!("{}::wakeup -> spawn in ", name)));
+        assert!(lines[3].starts_with(&format!("{}::timeout -> done in ", name)));
+    }
+}
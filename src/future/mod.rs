@@ -0,0 +1,437 @@
+use std::fmt;
+use std::any::Any;
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::marker::PhantomData;
+
+use rotor::{Notifier, GenericScope, WakeupError};
+
+mod pool;
+mod port;
+mod shared;
+mod oneshot;
+mod resolve;
+mod request;
+
+pub use self::pool::{ThreadPool, PoolError};
+pub use self::port::{Port, RefPort, MakePort, MakeRefPort, new_ref};
+pub use self::shared::SharedFuture;
+pub use self::oneshot::{Oneshot, oneshot};
+pub use self::resolve::{Await, Resolve};
+pub use self::request::Request;
+
+trait ReadFuture<T> {
+    fn ready(&self) -> bool;
+    fn take(&mut self) -> Option<Result<T, Panicked>>;
+    fn subscribe(&mut self, notifier: Notifier);
+    /// Marks the future as panicked, if there is no value yet
+    fn poisoned(&mut self);
+    /// Only for debug trait
+    fn peek(&self) -> Option<&T>;
+    fn panic(&self) -> Option<&Panicked>;
+}
+
+/// Same as `ReadFuture` but for lock-free implementations
+trait ReadSlot<T> {
+    fn ready(&self) -> bool;
+    fn take(&self) -> Option<Result<T, Panicked>>;
+    /// Only for debug trait
+    fn peek(&self) -> Option<&Result<T, Panicked>>;
+}
+
+/// The outcome of the future which producer has panicked
+///
+/// Contains the panic message if it was a string (i.e. `panic!` with
+/// a formatted message). When the lock of the future is poisoned by some
+/// code outside of this module, the message is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panicked(Option<String>);
+
+pub trait MakeFuture<T> {
+    fn make_future(self) -> Future<T>;
+}
+
+pub trait GetNotifier {
+    fn get_notifier(self) -> Notifier;
+}
+
+impl<'a, T: GenericScope> GetNotifier for &'a mut T {
+    fn get_notifier(self) -> Notifier {
+        self.notifier()
+    }
+}
+impl GetNotifier for Notifier {
+    fn get_notifier(self) -> Notifier {
+        return self;
+    }
+}
+
+pub struct Future<T>(Inner<T>);
+
+enum Inner<T> {
+    Locked(Arc<Mutex<dyn ReadFuture<T>>>),
+    Oneshot(Arc<dyn ReadSlot<T>>),
+}
+
+/// The shared state of the future, created by `future::new`
+///
+/// The consumer is woken up with `Panicked` only if the producer panics
+/// inside `Port::put`, a `ThreadPool` job or `put_with`. If the producer
+/// panics while holding the lock of this structure in any other way, the
+/// lock is just poisoned: the consumer notices that when it polls the
+/// future next time, but it isn't woken up for that.
+pub struct FutureImpl<I, O, F: FnOnce(I) -> O>{
+    output: Option<O>,
+    panic: Option<Panicked>,
+    convert: Option<F>,
+    notifiers: Vec<Notifier>,
+    phantom: PhantomData<fn(I)>,
+}
+
+impl<I, O, F> MakeFuture<O> for Arc<Mutex<FutureImpl<I, O, F>>>
+    where I: 'static, O: 'static, F: FnOnce(I) -> O + 'static
+{
+    fn make_future(self) -> Future<O> {
+        Future(Inner::Locked(self))
+    }
+}
+
+impl<I, O, F:FnOnce(I) -> O> FutureImpl<I, O, F> {
+    pub fn new(fun: F, notify: Notifier) -> FutureImpl<I, O, F> {
+        FutureImpl {
+            output: None,
+            panic: None,
+            convert: Some(fun),
+            notifiers: vec![notify],
+            phantom: PhantomData,
+        }
+    }
+    pub fn put(&mut self, t: O) {
+        self.output = Some(t);
+        self.wakeup();
+    }
+    /// Marks the future as failed, because the producer has panicked
+    pub fn fail(&mut self, panic: Panicked) {
+        self.panic = Some(panic);
+        self.wakeup();
+    }
+    pub fn convert(&mut self) -> F {
+        self.convert.take().unwrap()
+    }
+    /// Computes the input, converts it and puts the result into the future
+    ///
+    /// If either `producer` or the converter panics, the consumer is woken
+    /// up and gets `Panicked`, and the panic is propagated to the caller.
+    /// Use it instead of `put` when computing the value may panic while
+    /// the lock is held.
+    pub fn put_with<P: FnOnce() -> I>(&mut self, producer: P) {
+        let convert = self.convert();
+        match panic::catch_unwind(AssertUnwindSafe(|| convert(producer()))) {
+            Ok(value) => self.put(value),
+            Err(e) => {
+                self.fail(Panicked::from_payload(&*e));
+                panic::resume_unwind(e);
+            }
+        }
+    }
+    fn wakeup(&self) {
+        for notifier in &self.notifiers {
+            notify(notifier);
+        }
+    }
+}
+
+impl<I, O, F:FnOnce(I) -> O> ReadFuture<O> for FutureImpl<I, O, F> {
+    fn ready(&self) -> bool {
+        self.output.is_some() || self.panic.is_some()
+    }
+    fn take(&mut self) -> Option<Result<O, Panicked>> {
+        match self.output.take() {
+            Some(x) => Some(Ok(x)),
+            None => self.panic.clone().map(Err),
+        }
+    }
+    fn peek(&self) -> Option<&O> {
+        self.output.as_ref()
+    }
+    fn panic(&self) -> Option<&Panicked> {
+        self.panic.as_ref()
+    }
+    fn poisoned(&mut self) {
+        if self.output.is_none() && self.panic.is_none() {
+            self.panic = Some(Panicked(None));
+        }
+    }
+    fn subscribe(&mut self, notifier: Notifier) {
+        if self.ready() {
+            notify(&notifier);
+        }
+        self.notifiers.push(notifier);
+    }
+}
+
+impl<T: Sized> Future<T> {
+    /// Returns true if either value is ready or producer has panicked
+    pub fn is_done(&self) -> bool {
+        match self.0 {
+            Inner::Locked(ref fut) => lock(fut).ready(),
+            Inner::Oneshot(ref slot) => slot.ready(),
+        }
+    }
+    /// Takes the value out of the future
+    ///
+    /// Returns the future back, if it's not done yet. If the producer has
+    /// panicked (or the lock was poisoned in any other way) returns
+    /// `Panicked` instead of propagating the panic to the loop thread.
+    pub fn consume(self) -> Result<Result<T, Panicked>, Self> {
+        let value = match self.0 {
+            Inner::Locked(ref fut) => lock(fut).take(),
+            Inner::Oneshot(ref slot) => slot.take(),
+        };
+        if let Some(x) = value {
+            return Ok(x);
+        }
+        Err(self)
+    }
+}
+
+impl Panicked {
+    /// Creates a value from the payload of `panic::catch_unwind`
+    pub fn from_payload(payload: &(dyn Any + Send)) -> Panicked {
+        if let Some(s) = payload.downcast_ref::<&str>() {
+            Panicked(Some(s.to_string()))
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            Panicked(Some(s.clone()))
+        } else {
+            Panicked(None)
+        }
+    }
+    /// The panic message, if known
+    pub fn message(&self) -> Option<&str> {
+        self.0.as_ref().map(|x| &x[..])
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref msg) => write!(fmt, "producer panicked: {}", msg),
+            None => write!(fmt, "producer panicked"),
+        }
+    }
+}
+
+impl Error for Panicked {}
+
+/// Wakes up the consumer of the future
+///
+/// The consumer may live in a different loop. If that loop is already shut
+/// down there is nobody to deliver the value to, so the error is ignored.
+fn notify(notifier: &Notifier) {
+    match notifier.wakeup() {
+        Ok(()) | Err(WakeupError::Closed) => {}
+        Err(e) => panic!("wakeup of state machine: {}", e),
+    }
+}
+
+/// Locks the future, recovering from the poisoned lock
+fn lock<T>(future: &Mutex<dyn ReadFuture<T>>)
+    -> MutexGuard<'_, dyn ReadFuture<T> + 'static>
+{
+    future.lock().unwrap_or_else(|poisoned| {
+        let mut guard = poisoned.into_inner();
+        guard.poisoned();
+        guard
+    })
+}
+
+pub fn new<I, O, F, N>(notifier: N, fun: F)
+    -> Arc<Mutex<FutureImpl<I, O, F>>>
+    where O: 'static, F: FnOnce(I) -> O + 'static, N: GetNotifier
+{
+    Arc::new(Mutex::new(FutureImpl::new(fun, notifier.get_notifier())))
+}
+
+impl<T: fmt::Debug> fmt::Debug for Future<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Inner::Locked(ref fut) => {
+                let locked = lock(fut);
+                match (locked.peek(), locked.panic()) {
+                    (Some(value), _) => write!(fmt, "Future({:?})", value),
+                    (None, Some(_)) => write!(fmt, "Future(<Panicked>)"),
+                    (None, None) => write!(fmt, "Future(<Waiting>)"),
+                }
+            }
+            Inner::Oneshot(ref slot) => match slot.peek() {
+                Some(Ok(value)) => write!(fmt, "Future({:?})", value),
+                Some(Err(_)) => write!(fmt, "Future(<Panicked>)"),
+                None => write!(fmt, "Future(<Waiting>)"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+    use std::thread;
+    use std::time::Duration;
+
+    use rotor::{Machine, Scope, Response, EventSet};
+    use rotor::void::{Void, unreachable};
+
+    use super::{new, new_ref, oneshot, Future, MakeFuture, MakeRefPort};
+    use super::{ThreadPool, Await, Resolve};
+
+    #[derive(Debug)]
+    enum Fsm {
+        Waiting(Await<u64, Waiter>),
+        Done(u64),
+    }
+
+    #[derive(Debug)]
+    struct Waiter(u64);
+
+    impl Resolve<u64> for Waiter {
+        type Machine = Fsm;
+        fn resolved(self, value: u64, _scope: &mut Scope<()>)
+            -> Response<Fsm, Void>
+        {
+            Response::ok(Fsm::Done(self.0 + value))
+        }
+    }
+
+    impl Machine for Fsm {
+        type Context = ();
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, scope: &mut Scope<()>)
+            -> Response<Self, Void>
+        {
+            self.wakeup(scope)
+        }
+        fn spawned(self, scope: &mut Scope<()>) -> Response<Self, Void> {
+            self.wakeup(scope)
+        }
+        fn timeout(self, scope: &mut Scope<()>) -> Response<Self, Void> {
+            self.wakeup(scope)
+        }
+        fn wakeup(self, scope: &mut Scope<()>) -> Response<Self, Void> {
+            match self {
+                Fsm::Waiting(w) => w.poll(scope, Fsm::Waiting),
+                Fsm::Done(x) => Response::ok(Fsm::Done(x)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_int() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let ref mut scope = lp.scope(1);
+        let arc = new_ref(scope, |x: &str| x.parse().unwrap());
+        let future: Future<u64> = arc.clone().make_future();
+        let value: String = String::from("10");
+        arc.make_ref_port().put(&value[..]);
+        assert_eq!(future.consume().unwrap(), Ok(10));
+    }
+
+    #[test]
+    fn test_pool() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let scope = &mut lp.scope(1);
+        let pool = ThreadPool::new(2, 10);
+        let future = pool.spawn(scope, || 2 + 2).unwrap();
+        while !future.is_done() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(future.consume().unwrap(), Ok(4));
+    }
+
+    #[test]
+    #[should_panic(expected="at least one thread")]
+    fn test_pool_no_threads() {
+        ThreadPool::new(0, 10);
+    }
+
+    #[test]
+    fn test_pool_panic() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let scope = &mut lp.scope(1);
+        let pool = ThreadPool::new(1, 10);
+        let future = pool.spawn(&mut *scope, || -> u64 { panic!("oops") })
+            .unwrap();
+        while !future.is_done() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let err = future.consume().unwrap().unwrap_err();
+        assert_eq!(err.message(), Some("oops"));
+        // the worker is still alive
+        let future = pool.spawn(scope, || 1).unwrap();
+        while !future.is_done() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(future.consume().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn test_put_with_panic() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let arc = new(&mut lp.scope(1), |x: u64| x);
+        let future: Future<u64> = arc.clone().make_future();
+        let result = thread::spawn(move || {
+            arc.lock().unwrap().put_with(|| -> u64 { panic!("oops") })
+        }).join();
+        assert!(result.is_err());
+        assert!(future.is_done());
+        let err = future.consume().unwrap().unwrap_err();
+        assert_eq!(err.message(), Some("oops"));
+    }
+
+    #[test]
+    fn test_oneshot() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let (slot, future) = oneshot::<u64, _>(&mut lp.scope(1));
+        assert!(!future.is_done());
+        let future = future.shared().unwrap_err();
+        let future = future.consume().unwrap_err();
+        assert_eq!(slot.put(5), Ok(()));
+        assert_eq!(slot.put(6), Err(6));
+        assert!(future.is_done());
+        assert_eq!(future.consume().unwrap(), Ok(5));
+    }
+
+    #[test]
+    fn test_await() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let (slot, future) = oneshot(&mut lp.scope(1));
+        let fsm = Fsm::Waiting(Await::new(future, Waiter(1)));
+        // spurious wakeup
+        let fsm = fsm.wakeup(&mut lp.scope(1)).expect_machine();
+        match fsm {
+            Fsm::Waiting(ref w) => assert_eq!(w.state().0, 1),
+            _ => panic!("future is not done yet"),
+        }
+        slot.put(10).unwrap();
+        match fsm.wakeup(&mut lp.scope(1)).expect_machine() {
+            Fsm::Done(x) => assert_eq!(x, 11),
+            _ => panic!("future must be resolved"),
+        }
+    }
+
+    #[test]
+    fn test_shared() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let arc = new(&mut lp.scope(1), |x: u64| x);
+        let first = arc.clone().make_future().shared().unwrap();
+        let second = first.subscribe(&mut lp.scope(2));
+        assert!(!second.is_done());
+        arc.lock().unwrap().put(7);
+        assert_eq!(first.get(), Some(Ok(7)));
+        assert_eq!(second.get(), Some(Ok(7)));
+    }
+
+}
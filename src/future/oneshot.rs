@@ -0,0 +1,99 @@
+//! Lock-free single-slot future
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rotor::Notifier;
+
+use super::{Future, Inner, ReadSlot, GetNotifier, Panicked};
+use super::notify;
+
+
+const EMPTY: usize = 0;
+const WRITING: usize = 1;
+const READY: usize = 2;
+const TAKEN: usize = 3;
+
+/// A producer side of the lock-free future
+///
+/// Unlike `FutureImpl` it has no converter function and may only be written
+/// once. But checking whether value is ready is just an atomic load, and
+/// neither producer nor consumer ever takes a lock.
+///
+/// Note: future made from the oneshot can't be `shared()`.
+pub struct Oneshot<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<Option<Result<T, Panicked>>>,
+    notifier: Notifier,
+}
+
+// The value is written only by the side that moved state EMPTY -> WRITING
+// and read only by the single `Future` made in `oneshot()`, which never
+// leaves the consumer's thread, so `peek` and `take` can't race
+unsafe impl<T: Send> Send for Oneshot<T> {}
+unsafe impl<T: Send> Sync for Oneshot<T> {}
+
+impl<T> Oneshot<T> {
+    /// Puts the value and wakes up the state machine
+    ///
+    /// Returns value back if something was already put into the future.
+    pub fn put(&self, value: T) -> Result<(), T> {
+        self.write(Ok(value)).map_err(|v| match v {
+            Ok(v) => v,
+            Err(_) => unreachable!(),
+        })
+    }
+    /// Marks the future as failed, because the producer has panicked
+    ///
+    /// Does nothing if something was already put into the future.
+    pub fn fail(&self, panic: Panicked) {
+        self.write(Err(panic)).ok();
+    }
+    fn write(&self, value: Result<T, Panicked>)
+        -> Result<(), Result<T, Panicked>>
+    {
+        if self.state.compare_exchange(EMPTY, WRITING,
+            Ordering::Acquire, Ordering::Relaxed).is_err()
+        {
+            return Err(value);
+        }
+        unsafe { *self.value.get() = Some(value); }
+        self.state.store(READY, Ordering::Release);
+        notify(&self.notifier);
+        Ok(())
+    }
+}
+
+impl<T> ReadSlot<T> for Oneshot<T> {
+    fn ready(&self) -> bool {
+        self.state.load(Ordering::Acquire) >= READY
+    }
+    fn take(&self) -> Option<Result<T, Panicked>> {
+        if self.state.compare_exchange(READY, TAKEN,
+            Ordering::Acquire, Ordering::Relaxed).is_err()
+        {
+            return None;
+        }
+        unsafe { (*self.value.get()).take() }
+    }
+    fn peek(&self) -> Option<&Result<T, Panicked>> {
+        if self.state.load(Ordering::Acquire) != READY {
+            return None;
+        }
+        unsafe { (*self.value.get()).as_ref() }
+    }
+}
+
+/// Creates a lock-free future which is fulfilled with `Oneshot::put`
+///
+/// Returns the producer and the only consumer of the value.
+pub fn oneshot<T, N>(notifier: N) -> (Arc<Oneshot<T>>, Future<T>)
+    where T: Send + 'static, N: GetNotifier
+{
+    let slot = Arc::new(Oneshot {
+        state: AtomicUsize::new(EMPTY),
+        value: UnsafeCell::new(None),
+        notifier: notifier.get_notifier(),
+    });
+    (slot.clone(), Future(Inner::Oneshot(slot)))
+}
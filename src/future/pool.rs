@@ -0,0 +1,93 @@
+//! A thread pool which delivers results of the jobs as futures
+use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TrySendError};
+
+use super::{new, Future, MakeFuture, GetNotifier, Panicked};
+use util::lock;
+
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Error returned from `ThreadPool::spawn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    /// The queue of jobs is full, try again later
+    Full,
+    /// All worker threads are gone
+    Closed,
+}
+
+/// A fixed size pool of threads with a bounded queue of jobs
+///
+/// This is useful to offload CPU-heavy or blocking work (like filesystem
+/// access) off the loop thread. The result of the job is returned as a
+/// `Future` which wakes up the state machine when the value is ready.
+///
+/// When the pool is dropped worker threads finish the jobs which are already
+/// queued and exit.
+pub struct ThreadPool {
+    queue: SyncSender<Job>,
+}
+
+impl ThreadPool {
+    /// Starts `threads` workers sharing a queue of at most `queue_size` jobs
+    ///
+    /// Panics if `threads` is zero, as jobs would never be run.
+    pub fn new(threads: usize, queue_size: usize) -> ThreadPool {
+        assert!(threads > 0, "thread pool needs at least one thread");
+        let (tx, rx) = sync_channel(queue_size);
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..threads {
+            let rx = rx.clone();
+            thread::spawn(move || worker(rx));
+        }
+        ThreadPool { queue: tx }
+    }
+    /// Runs `fun` in the pool and returns a future of it's result
+    ///
+    /// The state machine which `notifier` belongs to is woken up when the
+    /// value is ready. The method never blocks, instead it returns
+    /// `PoolError::Full` if there are already `queue_size` jobs waiting.
+    ///
+    /// If the job panics, the consumer is woken up and gets `Panicked`
+    /// from the future.
+    pub fn spawn<T, F, N>(&self, notifier: N, fun: F)
+        -> Result<Future<T>, PoolError>
+        where T: Send + 'static,
+              F: FnOnce() -> T + Send + 'static,
+              N: GetNotifier,
+    {
+        let port = new(notifier, |x: T| x);
+        let future = port.clone().make_future();
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(fun));
+            let mut future = lock(&port);
+            match result {
+                Ok(value) => {
+                    let value = future.convert()(value);
+                    future.put(value);
+                }
+                Err(e) => future.fail(Panicked::from_payload(&*e)),
+            }
+        });
+        match self.queue.try_send(job) {
+            Ok(()) => Ok(future),
+            Err(TrySendError::Full(_)) => Err(PoolError::Full),
+            Err(TrySendError::Disconnected(_)) => Err(PoolError::Closed),
+        }
+    }
+}
+
+fn worker(queue: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match queue.lock().expect("queue can be locked").recv() {
+            Ok(job) => job,
+            // pool is dropped
+            Err(_) => break,
+        };
+        // keep the thread alive if even waking up the machine failed
+        panic::catch_unwind(AssertUnwindSafe(job)).ok();
+    }
+}
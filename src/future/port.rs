@@ -0,0 +1,105 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use super::{FutureImpl, GetNotifier, Panicked};
+use util::lock;
+
+
+trait Input<I> {
+    fn put_input(&mut self, input: I);
+}
+
+trait RefInput<I: ?Sized> {
+    fn put_ref(&mut self, input: &I);
+}
+
+/// A producer side of the future with output type erased
+///
+/// The port only knows the type of the input, the stored `convert` function
+/// is applied to the input to get the value for the future. This allows a
+/// protocol library to fulfill futures of any type that user asked for.
+pub struct Port<I>(Arc<Mutex<dyn Input<I> + Send>>);
+
+/// Same as `Port` but for converters that accept borrowed input
+///
+/// Created by `new_ref(..).make_ref_port()`. Unlike `Port<&'a T>`, this port
+/// is not bound to any lifetime, so it can be stored and then fed with
+/// a temporary value (i.e. a slice of the network buffer).
+pub struct RefPort<I: ?Sized>(Arc<Mutex<dyn RefInput<I> + Send>>);
+
+pub trait MakePort<I> {
+    fn make_port(self) -> Port<I>;
+}
+
+pub trait MakeRefPort<I: ?Sized> {
+    fn make_ref_port(self) -> RefPort<I>;
+}
+
+impl<I, O, F: FnOnce(I) -> O> Input<I> for FutureImpl<I, O, F> {
+    fn put_input(&mut self, input: I) {
+        self.put_with(|| input)
+    }
+}
+
+impl<I: ?Sized, O, F> RefInput<I> for FutureImpl<&'static I, O, F>
+    where F: for<'a> FnOnce(&'a I) -> O
+{
+    fn put_ref(&mut self, input: &I) {
+        let convert = self.convert();
+        match panic::catch_unwind(AssertUnwindSafe(|| convert(input))) {
+            Ok(value) => self.put(value),
+            Err(e) => {
+                self.fail(Panicked::from_payload(&*e));
+                panic::resume_unwind(e);
+            }
+        }
+    }
+}
+
+impl<I, O, F> MakePort<I> for Arc<Mutex<FutureImpl<I, O, F>>>
+    where I: 'static, O: Send + 'static, F: FnOnce(I) -> O + Send + 'static
+{
+    fn make_port(self) -> Port<I> {
+        Port(self)
+    }
+}
+
+impl<I, O, F> MakeRefPort<I> for Arc<Mutex<FutureImpl<&'static I, O, F>>>
+    where I: ?Sized + 'static, O: Send + 'static,
+          F: for<'a> FnOnce(&'a I) -> O + Send + 'static
+{
+    fn make_ref_port(self) -> RefPort<I> {
+        RefPort(self)
+    }
+}
+
+impl<I> Port<I> {
+    /// Converts the input and puts the result into the future
+    ///
+    /// If the converter panics, the consumer gets `Panicked` and the panic
+    /// is propagated to the caller.
+    pub fn put(self, input: I) {
+        lock(&self.0).put_input(input)
+    }
+}
+
+impl<I: ?Sized> RefPort<I> {
+    /// Converts the borrowed input and puts the result into the future
+    pub fn put(self, input: &I) {
+        lock(&self.0).put_ref(input)
+    }
+}
+
+/// Same as `future::new` but the converter accepts a borrowed input
+///
+/// Use `make_ref_port()` on the result to get the producer side. Having a
+/// separate constructor is needed to make the `fun` accept an input of any
+/// lifetime.
+pub fn new_ref<I, O, F, N>(notifier: N, fun: F)
+    -> Arc<Mutex<FutureImpl<&'static I, O, F>>>
+    where I: ?Sized + 'static, O: 'static,
+          F: for<'a> FnOnce(&'a I) -> O + 'static,
+          N: GetNotifier
+{
+    Arc::new(Mutex::new(FutureImpl::new(fun, notifier.get_notifier())))
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use super::{Future, Oneshot, GetNotifier, Panicked, oneshot};
+
+
+/// A request which is fulfilled by a state machine in another loop
+///
+/// The request is created in the loop of the requester, with it's
+/// `Notifier`, so the `Future` stays there. The request itself is sent to
+/// another thread (i.e. another loop) alongside with the request data. When
+/// the other side calls `reply()`, the requester is woken up in it's own
+/// loop.
+///
+/// If request is dropped without a reply (i.e. because of panic), the
+/// requester gets `Panicked` from the future.
+pub struct Request<Q, T> {
+    data: Q,
+    reply: Option<Arc<Oneshot<T>>>,
+}
+
+impl<Q, T: Send + 'static> Request<Q, T> {
+    /// Creates a request and a future for the reply to it
+    pub fn new<N: GetNotifier>(data: Q, notifier: N)
+        -> (Request<Q, T>, Future<T>)
+    {
+        let (slot, future) = oneshot(notifier);
+        (Request { data, reply: Some(slot) }, future)
+    }
+}
+
+impl<Q, T> Request<Q, T> {
+    pub fn data(&self) -> &Q {
+        &self.data
+    }
+    pub fn data_mut(&mut self) -> &mut Q {
+        &mut self.data
+    }
+    /// Sends the reply and wakes up the requester
+    pub fn reply(mut self, value: T) {
+        if let Some(slot) = self.reply.take() {
+            slot.put(value).ok();
+        }
+    }
+}
+
+impl<Q, T> Drop for Request<Q, T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.reply.take() {
+            slot.fail(Panicked(Some(
+                String::from("request dropped without reply"))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    use rotor::{Loop, Config, Machine, Scope, Response, EventSet, Notifier};
+    use rotor::void::{Void, unreachable};
+
+    use super::Request;
+    use future::Future;
+
+    type Req = Request<u64, u64>;
+
+    /// Doubles the value, shuts down the loop on zero
+    struct Server;
+
+    /// Asks for 21 doubled and reports the result to the channel
+    struct Client(Future<u64>, Sender<Req>, Notifier);
+
+    impl Machine for Server {
+        type Context = Receiver<Req>;
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<Receiver<Req>>)
+            -> Response<Self, Void>
+        {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Receiver<Req>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<Receiver<Req>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<Receiver<Req>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn wakeup(self, scope: &mut Scope<Receiver<Req>>)
+            -> Response<Self, Void>
+        {
+            while let Ok(req) = scope.try_recv() {
+                let value = *req.data();
+                if value == 0 {
+                    scope.shutdown_loop();
+                    return Response::done();
+                }
+                req.reply(value * 2);
+            }
+            Response::ok(self)
+        }
+    }
+
+    impl Machine for Client {
+        type Context = Sender<u64>;
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<Sender<u64>>)
+            -> Response<Self, Void>
+        {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Sender<u64>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn spawned(self, _scope: &mut Scope<Sender<u64>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<Sender<u64>>)
+            -> Response<Self, Void>
+        {
+            unreachable!();
+        }
+        fn wakeup(self, scope: &mut Scope<Sender<u64>>)
+            -> Response<Self, Void>
+        {
+            let Client(future, requests, server) = self;
+            match future.consume() {
+                Ok(value) => {
+                    scope.send(value.unwrap()).unwrap();
+                    // stop the server
+                    let (req, _) = Request::new(0, &mut *scope);
+                    requests.send(req).unwrap();
+                    server.wakeup().unwrap();
+                    scope.shutdown_loop();
+                    Response::done()
+                }
+                Err(future) => {
+                    Response::ok(Client(future, requests, server))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_two_loops() {
+        let (req_tx, req_rx) = channel();
+        let (notify_tx, notify_rx) = channel();
+        let (result_tx, result_rx) = channel();
+        let server = thread::spawn(move || {
+            let creator = Loop::<Server>::new(&Config::new()).unwrap();
+            let mut inst = creator.instantiate(req_rx);
+            inst.add_machine_with(|scope| {
+                notify_tx.send(scope.notifier()).unwrap();
+                Response::ok(Server)
+            }).unwrap();
+            inst.run().unwrap();
+        });
+        let server_notifier = notify_rx.recv().unwrap();
+        let client = thread::spawn(move || {
+            let creator = Loop::<Client>::new(&Config::new()).unwrap();
+            let mut inst = creator.instantiate(result_tx);
+            inst.add_machine_with(|scope| {
+                let (req, future) = Request::new(21, &mut *scope);
+                req_tx.send(req).unwrap();
+                server_notifier.wakeup().unwrap();
+                Response::ok(Client(future, req_tx, server_notifier))
+            }).unwrap();
+            inst.run().unwrap();
+        });
+        assert_eq!(result_rx.recv().unwrap(), 42);
+        client.join().unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use rotor::{Machine, Scope, Response};
+
+use super::{Future, Panicked};
+
+
+/// A protocol for the state that is waiting for a future
+///
+/// The implementation is usually a part of the state machine which has
+/// issued a request, and it turns into the next state when the result
+/// is ready.
+pub trait Resolve<T>: Sized {
+    /// The state machine that contains `Await<T, Self>`
+    type Machine: Machine;
+
+    /// Called when the value of the future is ready
+    fn resolved(self, value: T,
+        scope: &mut Scope<<Self::Machine as Machine>::Context>)
+        -> Response<Self::Machine, <Self::Machine as Machine>::Seed>;
+
+    /// Called when the producer of the future has panicked
+    ///
+    /// Default implementation stops the state machine with an error
+    fn panicked(self, panic: Panicked,
+        _scope: &mut Scope<<Self::Machine as Machine>::Context>)
+        -> Response<Self::Machine, <Self::Machine as Machine>::Seed>
+    {
+        Response::error(Box::new(panic))
+    }
+}
+
+/// A state of the machine which waits for a future
+///
+/// Call `Await::poll` from every event handler of the state machine while
+/// it's in this state. All events that come before the value is ready
+/// (including spurious ones) are ignored. When the future is done
+/// `Resolve::resolved` is called.
+///
+/// ```ignore
+/// enum Fsm {
+///     Fetching(Await<Response, Request>),
+///     ...
+/// }
+///
+/// fn wakeup(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+///     match self {
+///         Fsm::Fetching(w) => w.poll(scope, Fsm::Fetching),
+///         ...
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Await<T, M> {
+    future: Future<T>,
+    state: M,
+}
+
+impl<T, M: Resolve<T>> Await<T, M> {
+    pub fn new(future: Future<T>, state: M) -> Await<T, M> {
+        Await { future, state }
+    }
+    /// Returns the state that will receive the value
+    pub fn state(&self) -> &M {
+        &self.state
+    }
+    /// Checks the future and calls `Resolve::resolved` if it's done
+    ///
+    /// The `wrap` is used to put the `Await` back into the state machine
+    /// if value is not ready yet. Usually it's an enum variant of the
+    /// state machine.
+    pub fn poll<W>(self, scope: &mut Scope<<M::Machine as Machine>::Context>,
+        wrap: W)
+        -> Response<M::Machine, <M::Machine as Machine>::Seed>
+        where W: FnOnce(Await<T, M>) -> M::Machine
+    {
+        let Await { future, state } = self;
+        match future.consume() {
+            Ok(Ok(value)) => state.resolved(value, scope),
+            Ok(Err(panic)) => state.panicked(panic, scope),
+            Err(future) => Response::ok(wrap(Await { future, state })),
+        }
+    }
+}
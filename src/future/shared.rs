@@ -0,0 +1,62 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::{Future, Inner, ReadFuture, GetNotifier, Panicked, lock};
+
+
+/// A future which value may be observed by multiple state machines
+///
+/// Created by `Future::shared`. Every subscribed state machine is woken up
+/// when the value is put, and each of them gets a clone of the value.
+pub struct SharedFuture<T>(Arc<Mutex<dyn ReadFuture<T>>>);
+
+impl<T> Future<T> {
+    /// Converts the future into one that can be observed by many machines
+    ///
+    /// The state machine that created the future is still notified. Use
+    /// `SharedFuture::subscribe` to add other ones.
+    ///
+    /// Lock-free futures (created by `oneshot` or `Request`) have only one
+    /// notifier, so they can't be shared and are returned back as an error.
+    pub fn shared(self) -> Result<SharedFuture<T>, Future<T>> {
+        match self.0 {
+            Inner::Locked(fut) => Ok(SharedFuture(fut)),
+            inner @ Inner::Oneshot(_) => Err(Future(inner)),
+        }
+    }
+}
+
+impl<T: Clone> SharedFuture<T> {
+    /// Subscribes another state machine to the value of the future
+    ///
+    /// If the value is already there, the machine is woken up immediately
+    pub fn subscribe<N: GetNotifier>(&self, notifier: N) -> SharedFuture<T> {
+        lock(&self.0).subscribe(notifier.get_notifier());
+        SharedFuture(self.0.clone())
+    }
+    pub fn is_done(&self) -> bool {
+        lock(&self.0).ready()
+    }
+    /// Returns a clone of the value if it's already there
+    ///
+    /// Returns `Panicked` if the producer has panicked.
+    pub fn get(&self) -> Option<Result<T, Panicked>> {
+        let locked = lock(&self.0);
+        match (locked.peek(), locked.panic()) {
+            (Some(value), _) => Some(Ok(value.clone())),
+            (None, Some(panic)) => Some(Err(panic.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedFuture<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let locked = lock(&self.0);
+        match (locked.peek(), locked.panic()) {
+            (Some(value), _) => write!(fmt, "SharedFuture({:?})", value),
+            (None, Some(_)) => write!(fmt, "SharedFuture(<Panicked>)"),
+            (None, None) => write!(fmt, "SharedFuture(<Waiting>)"),
+        }
+    }
+}
@@ -1,4 +1,6 @@
 extern crate rotor;
+#[cfg(feature="derive")] extern crate rotor_tools_derive;
+#[cfg(feature="log")] #[macro_use] extern crate log;
 
 pub mod timer;
 pub mod sync;
@@ -6,3 +8,8 @@ pub mod loop_ext;
 pub mod compose;
 pub mod uniform;
 pub mod future;
+pub mod mailbox;
+pub mod shutdown;
+pub mod record;
+
+mod util;
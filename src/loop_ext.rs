@@ -1,7 +1,12 @@
 //! The traits which make main loop construction nicer
+use std::io;
+use std::time::Duration;
+
 use rotor::{Machine, Scope, EarlyScope, Loop, LoopInstance, SpawnError};
 use rotor::{Response, Void};
 
+use shutdown::{Shutdown, Drain};
+
 
 /// Convenience enhancements to the main loop creator
 pub trait LoopExt<M> {
@@ -79,6 +84,24 @@ pub trait LoopInstanceExt<M: Machine> {
         -> Result<T, SpawnError<()>>
         where W: FnOnce(N) -> M,
               F: FnOnce(&mut Scope<M::Context>) -> Response<(N, T), Void>;
+
+    /// Runs the loop until all machines are drained after shutdown
+    ///
+    /// After `shutdown.request()` is called, the loop is stopped when all
+    /// machines registered in `shutdown` are dropped, or when the `grace`
+    /// period is expired, whichever comes first.
+    ///
+    /// The `fsm_wrapper` puts the `Drain` machine into your state machine
+    /// type:
+    ///
+    /// ```ignore
+    /// loop_inst.run_until_drained(Fsm::Drain, &shutdown,
+    ///     Duration::from_secs(30))
+    /// ```
+    fn run_until_drained<W>(self, fsm_wrapper: W, shutdown: &Shutdown,
+        grace: Duration)
+        -> Result<(), io::Error>
+        where W: FnOnce(Drain<M::Context>) -> M;
 }
 
 impl<M: Machine> LoopExt<M> for Loop<M> {
@@ -114,4 +137,16 @@ impl<M: Machine> LoopInstanceExt<M> for LoopInstance<M> {
         }));
         Ok(result_opt.unwrap())
     }
+    fn run_until_drained<W>(mut self, fsm_wrapper: W, shutdown: &Shutdown,
+        grace: Duration)
+        -> Result<(), io::Error>
+        where W: FnOnce(Drain<M::Context>) -> M
+    {
+        // `io::Error::other` needs newer Rust
+        #[allow(clippy::io_other_error)]
+        self.add_machine_with(|scope| {
+            Drain::new(shutdown, grace, scope).wrap(fsm_wrapper)
+        }).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        self.run()
+    }
 }
@@ -0,0 +1,187 @@
+//! A queue of messages for a state machine
+//!
+//! Unlike a bare `Notifier`, the mailbox allows to send values to the state
+//! machine. The state machine drains the mailbox in it's `wakeup` handler.
+//!
+//! Wakeups are coalesced: only the first message sent after the mailbox was
+//! drained wakes up the state machine. So the machine must call
+//! `Mailbox::try_recv` until it returns `None`.
+//!
+//! ```ignore
+//! fn wakeup(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+//!     while let Some(msg) = self.mailbox.try_recv() {
+//!         // process message
+//!     }
+//!     Response::ok(self)
+//! }
+//! ```
+use std::fmt;
+use std::error::Error;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use rotor::{Notifier, WakeupError};
+
+use future::GetNotifier;
+use util::lock;
+
+
+struct State<T> {
+    queue: VecDeque<T>,
+    notified: bool,
+    closed: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    notifier: Notifier,
+}
+
+/// The receiving side of the mailbox, owned by the state machine
+pub struct Mailbox<T>(Arc<Shared<T>>);
+
+/// The sending side of the mailbox
+///
+/// May be cloned and sent to other threads.
+pub struct Sender<T>(Arc<Shared<T>>);
+
+/// Error returned by `Sender::send`, contains the message that wasn't sent
+pub enum SendError<T> {
+    /// There are already `capacity` messages in the mailbox or the
+    /// notification queue of the loop is full
+    Full(T),
+    /// The mailbox is dropped or the loop is shut down
+    Closed(T),
+}
+
+impl<T> Mailbox<T> {
+    /// Creates a mailbox for the state machine `notifier` belongs to
+    ///
+    /// At most `capacity` messages may be queued.
+    pub fn new<N: GetNotifier>(notifier: N, capacity: usize)
+        -> (Sender<T>, Mailbox<T>)
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                notified: false,
+                closed: false,
+            }),
+            capacity,
+            notifier: notifier.get_notifier(),
+        });
+        (Sender(shared.clone()), Mailbox(shared))
+    }
+    /// Returns next message, if any
+    ///
+    /// The state machine is woken up again only after this method has
+    /// returned `None`.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut state = lock(&self.0.state);
+        let value = state.queue.pop_front();
+        if value.is_none() {
+            state.notified = false;
+        }
+        value
+    }
+}
+
+impl<T> Sender<T> {
+    /// Puts the message into the mailbox and wakes up the state machine
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut state = lock(&self.0.state);
+        if state.closed {
+            return Err(SendError::Closed(value));
+        }
+        if state.queue.len() >= self.0.capacity {
+            return Err(SendError::Full(value));
+        }
+        if !state.notified {
+            // The lock is held, so the machine can't drain the queue before
+            // the message is pushed
+            match self.0.notifier.wakeup() {
+                Ok(()) => state.notified = true,
+                Err(WakeupError::Full) => return Err(SendError::Full(value)),
+                Err(_) => {
+                    state.closed = true;
+                    return Err(SendError::Closed(value));
+                }
+            }
+        }
+        state.queue.push_back(value);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Mailbox<T> {
+    fn drop(&mut self) {
+        let mut state = lock(&self.0.state);
+        state.closed = true;
+        state.queue.clear();
+    }
+}
+
+impl<T> SendError<T> {
+    /// Returns the message that wasn't sent
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Full(x) => x,
+            SendError::Closed(x) => x,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SendError::Full(..) => write!(fmt, "Full(..)"),
+            SendError::Closed(..) => write!(fmt, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SendError::Full(..) => write!(fmt, "mailbox is full"),
+            SendError::Closed(..) => write!(fmt, "mailbox is closed"),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use super::{Mailbox, SendError};
+
+    #[test]
+    fn test_send() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let (tx, mailbox) = Mailbox::new(&mut lp.scope(1), 2);
+        tx.send(1).unwrap();
+        tx.clone().send(2).unwrap();
+        match tx.send(3) {
+            Err(SendError::Full(3)) => {}
+            _ => panic!("mailbox must be full"),
+        }
+        assert_eq!(mailbox.try_recv(), Some(1));
+        assert_eq!(mailbox.try_recv(), Some(2));
+        assert_eq!(mailbox.try_recv(), None);
+        tx.send(4).unwrap();
+        drop(mailbox);
+        match tx.send(5) {
+            Err(SendError::Closed(5)) => {}
+            _ => panic!("mailbox must be closed"),
+        }
+    }
+}
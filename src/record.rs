@@ -0,0 +1,483 @@
+//! Recording and replaying events of a state machine
+//!
+//! Wrap the machine into `Recorder` to write every event it receives, and
+//! the kind of the response it returned, into a `Log`. The log is
+//! serialized into a compact text form, one event per line:
+//!
+//! ```text
+//! 0 ready:r ok
+//! 3 wakeup spawn
+//! 3 spawned ok
+//! 15 timeout done
+//! ```
+//!
+//! The first column is the number of milliseconds since the state machine
+//! was created. The log may then be put into a regression test and replayed
+//! on a fresh machine with `replay()`:
+//!
+//! ```ignore
+//! let log = Log::parse(include_str!("stuck_connection.log")).unwrap();
+//! let mut lp = MockLoop::new(Context::new());
+//! let fsm = Connection::new(..);
+//! replay(fsm, &log.entries(), &mut lp.scope(1)).unwrap();
+//! ```
+use std::fmt;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use rotor::{Machine, EventSet, Scope, Response, SpawnError};
+use rotor::void::Void;
+
+use compose::ResponseKind;
+use util::lock;
+
+
+/// An event received by the state machine
+///
+/// Unlike `sync::Event` it includes `spawn_error`, as it's replayed too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordedEvent {
+    Ready(EventSet),
+    Spawned,
+    Timeout,
+    Wakeup,
+    SpawnError,
+}
+
+/// A single line of the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Milliseconds since the state machine was created
+    pub millis: u64,
+    pub event: RecordedEvent,
+    pub response: ResponseKind,
+}
+
+/// The log of events, shared between the recorder and its owner
+///
+/// May be cloned and sent to other threads. Its `Display` implementation
+/// writes the serialized form.
+#[derive(Clone, Default)]
+pub struct Log(Arc<Mutex<Vec<Entry>>>);
+
+/// Error parsing the serialized log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    line: usize,
+    message: &'static str,
+}
+
+/// The context of the recorded state machines
+///
+/// Used to get a log for the recorder created by `Machine::create`, i.e.
+/// when the machine is spawned by another machine. Return a new log for
+/// every machine (and keep a clone of it), if machines need to be replayed
+/// separately.
+pub trait Recording {
+    fn log(&mut self) -> Log;
+}
+
+/// A wrapper which records all events of the state machine into a log
+pub struct Recorder<M> {
+    machine: M,
+    log: Log,
+    start: Instant,
+}
+
+/// Error returned by `replay()` when the machine responds differently
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the entry in the log
+    pub index: usize,
+    pub expected: ResponseKind,
+    /// The kind of the response, or `None` if the machine has already
+    /// stopped before the event
+    pub actual: Option<ResponseKind>,
+}
+
+/// Error returned by `replay()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The machine responds differently than recorded
+    Mismatch(Mismatch),
+    /// The `spawn_error` entry with this index isn't preceded by a `spawn`
+    /// response, so there is no seed to pass to the machine
+    NoSeed(usize),
+}
+
+impl Log {
+    pub fn new() -> Log {
+        Log::default()
+    }
+    /// Parses a log written by `Display` implementation
+    pub fn parse(text: &str) -> Result<Log, ParseError> {
+        let mut entries = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(line.parse().map_err(|mut e: ParseError| {
+                e.line = idx + 1;
+                e
+            })?);
+        }
+        Ok(Log(Arc::new(Mutex::new(entries))))
+    }
+    /// Returns a copy of all entries recorded so far
+    pub fn entries(&self) -> Vec<Entry> {
+        self.lock().clone()
+    }
+    fn push(&self, entry: Entry) {
+        self.lock().push(entry);
+    }
+    fn lock(&self) -> MutexGuard<'_, Vec<Entry>> {
+        lock(&self.0)
+    }
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.lock().iter() {
+            writeln!(fmt, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Log {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Log({} entries)", self.lock().len())
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} ", self.millis)?;
+        match self.event {
+            RecordedEvent::Ready(events) => {
+                fmt.write_str("ready:")?;
+                for &(flag, set) in &flags() {
+                    if events.contains(set) {
+                        write!(fmt, "{}", flag)?;
+                    }
+                }
+            }
+            RecordedEvent::Spawned => fmt.write_str("spawned")?,
+            RecordedEvent::Timeout => fmt.write_str("timeout")?,
+            RecordedEvent::Wakeup => fmt.write_str("wakeup")?,
+            RecordedEvent::SpawnError => fmt.write_str("spawn_error")?,
+        }
+        write!(fmt, " {}", self.response)
+    }
+}
+
+fn flags() -> [(char, EventSet); 4] {
+    [
+        ('r', EventSet::readable()),
+        ('w', EventSet::writable()),
+        ('e', EventSet::error()),
+        ('h', EventSet::hup()),
+    ]
+}
+
+impl FromStr for Entry {
+    type Err = ParseError;
+    fn from_str(line: &str) -> Result<Entry, ParseError> {
+        let error = |message| ParseError { line: 0, message };
+        let mut words = line.split_whitespace();
+        let millis = words.next().and_then(|x| x.parse().ok())
+            .ok_or(error("bad timestamp"))?;
+        let event = match words.next() {
+            Some("spawned") => RecordedEvent::Spawned,
+            Some("timeout") => RecordedEvent::Timeout,
+            Some("wakeup") => RecordedEvent::Wakeup,
+            Some("spawn_error") => RecordedEvent::SpawnError,
+            Some(x) if x.starts_with("ready:") => {
+                let mut events = EventSet::none();
+                for c in x["ready:".len()..].chars() {
+                    let &(_, set) = flags().iter().find(|&&(f, _)| f == c)
+                        .ok_or(error("bad event set"))?;
+                    events.insert(set);
+                }
+                RecordedEvent::Ready(events)
+            }
+            _ => return Err(error("bad event")),
+        };
+        let response = match words.next() {
+            Some("ok") => ResponseKind::Ok,
+            Some("spawn") => ResponseKind::Spawn,
+            Some("done") => ResponseKind::Done,
+            Some("error") => ResponseKind::Error,
+            _ => return Err(error("bad response kind")),
+        };
+        if words.next().is_some() {
+            return Err(error("extra data at the end of line"));
+        }
+        Ok(Entry { millis, event, response })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.actual {
+            Some(kind) => write!(fmt, "entry {}: expected {}, got {}",
+                self.index, self.expected, kind),
+            None => write!(fmt, "entry {}: expected {}, machine is stopped",
+                self.index, self.expected),
+        }
+    }
+}
+
+impl Error for Mismatch {}
+
+impl From<Mismatch> for ReplayError {
+    fn from(mismatch: Mismatch) -> ReplayError {
+        ReplayError::Mismatch(mismatch)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Mismatch(ref m) => m.fmt(fmt),
+            ReplayError::NoSeed(index) => write!(fmt,
+                "entry {}: spawn_error without preceding spawn", index),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl<M> Recorder<M> {
+    pub fn new(machine: M, log: Log) -> Recorder<M> {
+        Recorder {
+            machine,
+            log,
+            start: Instant::now(),
+        }
+    }
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+    pub fn get_ref(&self) -> &M {
+        &self.machine
+    }
+    fn record<N>(log: Log, start: Instant, event: RecordedEvent,
+        response: Response<M, N>)
+        -> Response<Recorder<M>, N>
+    {
+        let elapsed = start.elapsed();
+        let (kind, response) = ResponseKind::of(response);
+        log.push(Entry {
+            millis: elapsed.as_secs() * 1000
+                + u64::from(elapsed.subsec_millis()),
+            event,
+            response: kind,
+        });
+        response.wrap(|machine| Recorder { machine, log, start })
+    }
+}
+
+impl<M: Machine> Machine for Recorder<M>
+    where M::Context: Recording,
+{
+    type Context = M::Context;
+    type Seed = M::Seed;
+
+    fn create(seed: M::Seed, scope: &mut Scope<M::Context>)
+        -> Response<Self, Void>
+    {
+        let log = scope.log();
+        M::create(seed, scope).wrap(|m| Recorder::new(m, log))
+    }
+    fn ready(self, events: EventSet, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let response = self.machine.ready(events, scope);
+        Recorder::record(self.log, self.start,
+            RecordedEvent::Ready(events), response)
+    }
+    fn spawned(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let response = self.machine.spawned(scope);
+        Recorder::record(self.log, self.start,
+            RecordedEvent::Spawned, response)
+    }
+    fn timeout(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let response = self.machine.timeout(scope);
+        Recorder::record(self.log, self.start,
+            RecordedEvent::Timeout, response)
+    }
+    fn wakeup(self, scope: &mut Scope<M::Context>)
+        -> Response<Self, M::Seed>
+    {
+        let response = self.machine.wakeup(scope);
+        Recorder::record(self.log, self.start,
+            RecordedEvent::Wakeup, response)
+    }
+    fn spawn_error(self, scope: &mut Scope<M::Context>,
+                   error: SpawnError<M::Seed>)
+        -> Response<Self, M::Seed>
+    {
+        let response = self.machine.spawn_error(scope, error);
+        Recorder::record(self.log, self.start,
+            RecordedEvent::SpawnError, response)
+    }
+}
+
+/// Drives the machine through the recorded events
+///
+/// Every response of the machine is checked against the recorded kind.
+/// The seed of the last `spawn` response is used for `spawn_error` event,
+/// `ReplayError::NoSeed` is returned if there is no such response.
+/// Timestamps are not replayed, the scope of the mock loop always has the
+/// same time. Returns the machine after the last event, or `None` if it's
+/// stopped.
+pub fn replay<M: Machine>(machine: M, entries: &[Entry],
+    scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, ReplayError>
+{
+    let mut machine = Some(machine);
+    let mut seed = None;
+    for (index, entry) in entries.iter().enumerate() {
+        let m = machine.take().ok_or(Mismatch {
+            index,
+            expected: entry.response,
+            actual: None,
+        })?;
+        let response = match entry.event {
+            RecordedEvent::Ready(events) => m.ready(events, scope),
+            RecordedEvent::Spawned => m.spawned(scope),
+            RecordedEvent::Timeout => m.timeout(scope),
+            RecordedEvent::Wakeup => m.wakeup(scope),
+            RecordedEvent::SpawnError => match seed.take() {
+                Some(s) => m.spawn_error(scope, SpawnError::NoSlabSpace(s)),
+                None => return Err(ReplayError::NoSeed(index)),
+            },
+        };
+        let (kind, response) = ResponseKind::of(response);
+        if kind != entry.response {
+            return Err(ReplayError::Mismatch(Mismatch {
+                index,
+                expected: entry.response,
+                actual: Some(kind),
+            }));
+        }
+        response.map(|m| machine = Some(m), |s| seed = Some(s));
+    }
+    Ok(machine)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use rotor::{Machine, EventSet, Scope, Response};
+    use rotor::void::Void;
+
+    use compose::ResponseKind;
+    use super::{Log, Recorder, Recording, RecordedEvent, Entry, replay};
+    use super::{Mismatch, ReplayError};
+    use util::test::machine;
+
+    struct Context(Log);
+
+    impl Recording for Context {
+        fn log(&mut self) -> Log {
+            self.0.clone()
+        }
+    }
+
+    /// Spawns on wakeup, stops after the number of reads in a seed
+    struct Reader(u32);
+
+    impl Machine for Reader {
+        type Context = Context;
+        type Seed = u32;
+        fn create(seed: u32, _scope: &mut Scope<Context>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Reader(seed))
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<Context>)
+            -> Response<Self, u32>
+        {
+            if self.0 <= 1 {
+                Response::done()
+            } else {
+                Response::ok(Reader(self.0 - 1))
+            }
+        }
+        fn spawned(self, _scope: &mut Scope<Context>) -> Response<Self, u32> {
+            Response::ok(self)
+        }
+        fn timeout(self, _scope: &mut Scope<Context>) -> Response<Self, u32> {
+            Response::ok(self)
+        }
+        fn wakeup(self, _scope: &mut Scope<Context>) -> Response<Self, u32> {
+            Response::spawn(self, 1)
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut lp = rotor_test::MockLoop::new(Context(Log::new()));
+        let fsm = machine(Recorder::<Reader>::create(2, &mut lp.scope(1)));
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        let fsm = machine(fsm.spawned(&mut lp.scope(1)));
+        let fsm = machine(fsm.ready(EventSet::readable() | EventSet::hup(),
+            &mut lp.scope(1)));
+        let fsm = machine(fsm.timeout(&mut lp.scope(1)));
+        assert!(fsm.ready(EventSet::readable(), &mut lp.scope(1))
+            .is_stopped());
+
+        let text = lp.ctx().0.to_string();
+        assert_eq!(text.lines().map(|l| &l[l.find(' ').unwrap() + 1..])
+            .collect::<Vec<_>>(), vec![
+                "wakeup spawn",
+                "spawned ok",
+                "ready:rh ok",
+                "timeout ok",
+                "ready:r done",
+            ]);
+        let log = Log::parse(&text).unwrap();
+        assert_eq!(log.entries(), lp.ctx().0.entries());
+        assert!(replay(Reader(2), &log.entries(), &mut lp.scope(2))
+            .unwrap().is_none());
+        assert_eq!(replay(Reader(3), &log.entries(), &mut lp.scope(2))
+            .err(), Some(ReplayError::Mismatch(Mismatch {
+                index: 4,
+                expected: ResponseKind::Done,
+                actual: Some(ResponseKind::Ok),
+            })));
+        let log = Log::parse("0 spawn_error ok").unwrap();
+        assert_eq!(replay(Reader(2), &log.entries(), &mut lp.scope(2))
+            .err(), Some(ReplayError::NoSeed(0)));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("10 ready:we error".parse(), Ok(Entry {
+            millis: 10,
+            event: RecordedEvent::Ready(
+                EventSet::writable() | EventSet::error()),
+            response: ResponseKind::Error,
+        }));
+        assert_eq!(Log::parse("1 wakeup ok\n2 wakeup\n").unwrap_err()
+            .to_string(), "line 2: bad response kind");
+        assert!(Log::parse("1 ready:x ok").is_err());
+    }
+}
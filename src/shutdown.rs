@@ -0,0 +1,311 @@
+//! Graceful shutdown of the state machines
+//!
+//! The `Shutdown` token is usually put into the context. Every state
+//! machine that needs to finish its work before the loop exits keeps a
+//! `Registration`, and checks `Shutdown::is_requested()` on wakeup:
+//!
+//! ```ignore
+//! fn wakeup(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+//!     if scope.shutdown.is_requested() {
+//!         // finish in-flight request and return Response::done()
+//!     }
+//!     ...
+//! }
+//! ```
+//!
+//! Use `LoopInstanceExt::run_until_drained` to run the loop until all
+//! registered machines are dropped, or the grace period is expired, after
+//! the shutdown is requested.
+use std::fmt;
+use std::marker::PhantomData;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use rotor::{Machine, EventSet, Scope, Response, Notifier, Time};
+use rotor::void::{Void, unreachable};
+
+use future::GetNotifier;
+use util::lock;
+
+
+struct Machines {
+    next_id: usize,
+    registered: HashMap<usize, Notifier>,
+    watchers: HashMap<usize, Notifier>,
+}
+
+struct State {
+    requested: AtomicBool,
+    machines: Mutex<Machines>,
+}
+
+/// A token used to request the shutdown and to observe it
+///
+/// The token may be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct Shutdown(Arc<State>);
+
+/// The state machine registered to be woken up on shutdown
+///
+/// The machine is unregistered when this value is dropped, so it's usually
+/// kept in the state machine itself.
+pub struct Registration {
+    shutdown: Shutdown,
+    id: usize,
+}
+
+/// The watcher woken up when all registered machines are drained
+///
+/// Like `Registration`, the watcher is removed when this value is dropped.
+struct Watch {
+    shutdown: Shutdown,
+    id: usize,
+}
+
+/// A state machine which stops the loop when all machines are drained
+///
+/// Created by `LoopInstanceExt::run_until_drained`. It's only useful to put
+/// it as a variant of your state machine.
+pub struct Drain<C> {
+    watch: Watch,
+    grace: Duration,
+    deadline: Option<Time>,
+    phantom: PhantomData<fn(&mut C)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown(Arc::new(State {
+            requested: AtomicBool::new(false),
+            machines: Mutex::new(Machines {
+                next_id: 0,
+                registered: HashMap::new(),
+                watchers: HashMap::new(),
+            }),
+        }))
+    }
+    /// Returns true if shutdown was requested
+    pub fn is_requested(&self) -> bool {
+        self.0.requested.load(Ordering::SeqCst)
+    }
+    /// Requests a shutdown and wakes up all registered state machines
+    ///
+    /// May be called from any thread.
+    pub fn request(&self) {
+        if self.0.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let machines = self.lock();
+        for notifier in machines.registered.values() {
+            notifier.wakeup().ok();
+        }
+        for notifier in machines.watchers.values() {
+            notifier.wakeup().ok();
+        }
+    }
+    /// Registers a state machine to be woken up on shutdown
+    ///
+    /// If shutdown is already requested the machine is woken up
+    /// immediately.
+    pub fn register<N: GetNotifier>(&self, notifier: N) -> Registration {
+        let notifier = notifier.get_notifier();
+        let mut machines = self.lock();
+        let id = machines.allocate_id();
+        if self.is_requested() {
+            notifier.wakeup().ok();
+        }
+        machines.registered.insert(id, notifier);
+        Registration {
+            shutdown: self.clone(),
+            id,
+        }
+    }
+    /// Number of registered state machines that are still alive
+    pub fn live(&self) -> usize {
+        self.lock().registered.len()
+    }
+    fn watch(&self, notifier: Notifier) -> Watch {
+        let mut machines = self.lock();
+        let id = machines.allocate_id();
+        machines.watchers.insert(id, notifier);
+        Watch {
+            shutdown: self.clone(),
+            id,
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, Machines> {
+        lock(&self.0.machines)
+    }
+}
+
+impl Machines {
+    fn allocate_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown::new()
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Shutdown")
+            .field("requested", &self.is_requested())
+            .field("live", &self.live())
+            .finish()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut machines = self.shutdown.lock();
+        machines.registered.remove(&self.id);
+        if machines.registered.is_empty() && self.shutdown.is_requested() {
+            for notifier in machines.watchers.values() {
+                notifier.wakeup().ok();
+            }
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.shutdown.lock().watchers.remove(&self.id);
+    }
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Registration({})", self.id)
+    }
+}
+
+impl<C> Drain<C> {
+    /// Creates a machine which stops the loop after all registered machines
+    /// are drained, or `grace` period has passed since shutdown request
+    pub fn new(shutdown: &Shutdown, grace: Duration, scope: &mut Scope<C>)
+        -> Response<Drain<C>, Void>
+    {
+        let me = Drain {
+            watch: shutdown.watch(scope.notifier()),
+            grace,
+            deadline: None,
+            phantom: PhantomData,
+        };
+        // machine can't be stopped in `create()`, so if shutdown is already
+        // requested the check is postponed till the first `timeout()`
+        if shutdown.is_requested() {
+            Response::ok(me).deadline(scope.now())
+        } else {
+            Response::ok(me)
+        }
+    }
+    fn check<S>(self, scope: &mut Scope<C>) -> Response<Drain<C>, S> {
+        let shutdown = &self.watch.shutdown;
+        if !shutdown.is_requested() {
+            return Response::ok(self);
+        }
+        let deadline = self.deadline
+            .unwrap_or_else(|| scope.now() + self.grace);
+        if shutdown.live() == 0 || scope.now() >= deadline {
+            scope.shutdown_loop();
+            return Response::done();
+        }
+        Response::ok(Drain { deadline: Some(deadline), ..self })
+            .deadline(deadline)
+    }
+}
+
+impl<C> Machine for Drain<C> {
+    type Context = C;
+    type Seed = Void;
+    fn create(seed: Void, _scope: &mut Scope<C>) -> Response<Self, Void> {
+        unreachable(seed)
+    }
+    fn ready(self, _events: EventSet, scope: &mut Scope<C>)
+        -> Response<Self, Void>
+    {
+        self.check(scope)
+    }
+    fn spawned(self, scope: &mut Scope<C>) -> Response<Self, Void> {
+        self.check(scope)
+    }
+    fn timeout(self, scope: &mut Scope<C>) -> Response<Self, Void> {
+        self.check(scope)
+    }
+    fn wakeup(self, scope: &mut Scope<C>) -> Response<Self, Void> {
+        self.check(scope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rotor_test;
+
+    use std::time::Duration;
+
+    use rotor::{Machine, Loop, Config};
+
+    use loop_ext::LoopInstanceExt;
+    use super::{Shutdown, Drain};
+    use util::test::machine;
+
+    #[test]
+    fn test_drain() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let shutdown = Shutdown::new();
+        let first = shutdown.register(&mut lp.scope(1));
+        let second = shutdown.register(&mut lp.scope(2));
+        let drain = machine(Drain::new(&shutdown, Duration::from_secs(10),
+            &mut lp.scope(3)));
+        let drain = machine(drain.wakeup(&mut lp.scope(3)));
+        shutdown.request();
+        assert!(shutdown.is_requested());
+        let drain = machine(drain.wakeup(&mut lp.scope(3)));
+        drop(first);
+        assert_eq!(shutdown.live(), 1);
+        let drain = machine(drain.wakeup(&mut lp.scope(3)));
+        drop(second);
+        assert!(drain.wakeup(&mut lp.scope(3)).is_stopped());
+        assert!(shutdown.lock().watchers.is_empty());
+    }
+
+    #[test]
+    fn test_grace() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let shutdown = Shutdown::new();
+        let _registration = shutdown.register(&mut lp.scope(1));
+        shutdown.request();
+        let drain = machine(Drain::new(&shutdown, Duration::new(0, 0),
+            &mut lp.scope(2)));
+        // time doesn't advance in the mock loop, so zero grace period is
+        // the only one that expires
+        assert!(drain.timeout(&mut lp.scope(2)).is_stopped());
+    }
+
+    #[test]
+    fn test_requested_before_run() {
+        let drained = Shutdown::new();
+        drained.request();
+        let inst = Loop::<Drain<()>>::new(&Config::new()).unwrap()
+            .instantiate(());
+        inst.run_until_drained(|d| d, &drained, Duration::from_secs(60))
+            .unwrap();
+
+        let shutdown = Shutdown::new();
+        let mut lp = rotor_test::MockLoop::new(());
+        let _registration = shutdown.register(&mut lp.scope(1));
+        shutdown.request();
+        let inst = Loop::<Drain<()>>::new(&Config::new()).unwrap()
+            .instantiate(());
+        inst.run_until_drained(|d| d, &shutdown, Duration::new(0, 0))
+            .unwrap();
+    }
+}
@@ -1,97 +1,633 @@
 //! Provides state machine that is guarded by Mutex
 //!
 //! This allows the machine to be manipulated from multiple threads. But you
-//! must be careful to not to break the state machine
-use std::mem;
-use std::sync::{Arc, Mutex};
+//! must be careful to not to break the state machine. Use `MutexedHandle`
+//! to access the machine from other threads safely.
+//!
+//! The `RwMutexed` is a variant guarded by `RwLock`, for machines that are
+//! only read by other threads. Its event handlers take a write lock, while
+//! `RwMutexedHandle::read` may be used by any number of threads at once.
+//!
+//! When the lock is poisoned the machine is restarted according to the
+//! `RestartPolicy` (see `Replaceable::restart_policy`), and
+//! `Replaceable::poisoned` is called for each event that is discarded.
+use std::fmt;
+use std::ops::Deref;
+use std::thread;
+use std::error::Error;
+use std::time::Duration;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use rotor::{Machine, EventSet, Scope, Response};
+use rotor::{Machine, EventSet, Scope, Response, Notifier, WakeupError, Time};
 use rotor::{Void};
 
-pub struct Mutexed<M>(pub Arc<Mutex<M>>);
+use future::GetNotifier;
+use util::lock;
+
+/// Derives `Replaceable` for an enum, enabled by the `derive` feature
+#[cfg(feature="derive")]
+pub use rotor_tools_derive::Replaceable;
+
+/// A lock which keeps the state machine, either `Mutex` or `RwLock`
+trait Lock {
+    type Machine;
+    /// Runs `fun` with the machine locked for writing
+    ///
+    /// Poisoning of the lock itself is ignored, `Shared::poisoned` is
+    /// used instead, as it can be reset after the restart.
+    fn locked<R, F>(&self, fun: F) -> R
+        where F: FnOnce(&mut Self::Machine) -> R;
+}
+
+struct Shared<L> {
+    machine: L,
+    /// Set when some thread panics while holding the lock
+    poisoned: AtomicBool,
+    restarts: AtomicUsize,
+    poisoned_by: Mutex<Option<String>>,
+}
+
+/// Marks the machine as poisoned if the thread panics while it's locked
+struct PanicGuard<'a, L: 'a>(&'a Shared<L>);
+
+pub struct Mutexed<M> {
+    shared: Arc<Shared<Mutex<Option<M>>>>,
+    supervisor: Supervisor,
+}
 
-/// A trait which allows to replace the state machine with dummy/null/None
+/// Same as `Mutexed` but guarded by `RwLock`
 ///
-/// Since we put machine (used with `Mutexed`) into a `Arc<Mutex<M>>` we need
-/// to replace it with some other value while the real value is moved off the
-/// state machine to be able to replace it by code, having self by-value.
+/// Event handlers of the state machine are run with a write lock held. Other
+/// threads can only read the machine using `RwMutexedHandle`.
+pub struct RwMutexed<M> {
+    shared: Arc<Shared<RwLock<Option<M>>>>,
+    supervisor: Supervisor,
+}
+
+/// Limits how often the poisoned state machine is restarted
 ///
-/// When the thread working with the state machine panics while machine is
-/// moved off the place, the poisoned lock is left with `Replaceable::empty`
-/// value. When lock is poisoned we unwrap the value and use it as a valid
-/// state machine. So you can continue to work after panic with the clean
-/// state after a crash (perhaps if the panic was in different thread).
+/// Default policy restarts the state machine every time the lock is
+/// poisoned, without any delay.
 ///
-/// The text above means you should be able to "restart" the state machine
-/// from the `empty()` state.
-pub trait Replaceable: Machine {
-    /// Return the empty value that may be used as replacement
+/// ```ignore
+/// RestartPolicy::new()
+///     .max_restarts(5, Duration::from_secs(60))
+///     .backoff(Duration::from_millis(100))
+///     .escalate(Escalate::Panic)
+/// ```
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    limit: Option<(usize, Duration)>,
+    backoff: Duration,
+    escalate: Escalate,
+    replay: bool,
+}
+
+/// What to do when the restart budget of `RestartPolicy` is exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalate {
+    /// Stop the state machine with `Response::done()`, handles get
+    /// `CallError::Stopped` afterwards
+    Stop,
+    /// Panic in the loop thread, i.e. propagate failure to the whole loop
+    Panic,
+}
+
+struct Supervisor {
+    policy: RestartPolicy,
+    recent: VecDeque<Time>,
+    last_restart: Option<Time>,
+    /// The first event discarded while waiting for the backoff
+    delayed: Option<Event>,
+}
+
+/// An event of the state machine, delivered by `Mutexed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Ready(EventSet),
+    Spawned,
+    Timeout,
+    Wakeup,
+}
+
+/// Describes the poisoned lock found by `Mutexed`
+#[derive(Debug)]
+pub struct PoisonInfo {
+    event: Event,
+    thread: Option<String>,
+    count: usize,
+}
+
+enum Verdict {
+    /// Restart now, the event is the one to replay
+    Restart(Event),
+    Delay(Time),
+    Escalate,
+}
+
+/// A handle to the `Mutexed` state machine used by other threads
+///
+/// Every call runs a closure with the state machine locked, and then wakes
+/// up the state machine, so it can act on the changes made. Since closure
+/// receives a reference, it can't move the machine out of the lock.
+pub struct MutexedHandle<M> {
+    shared: Arc<Shared<Mutex<Option<M>>>>,
+    notifier: Notifier,
+}
+
+/// A handle to the `RwMutexed` state machine used by other threads
+///
+/// Any number of threads may read the state machine at once. The machine
+/// is not woken up by reads.
+pub struct RwMutexedHandle<M> {
+    shared: Arc<Shared<RwLock<Option<M>>>>,
+    notifier: Notifier,
+}
+
+/// The read guard returned by `RwMutexedHandle::read`
+pub struct ReadGuard<'a, M: 'a>(RwLockReadGuard<'a, Option<M>>);
+
+/// Error returned from `MutexedHandle::call` and `RwMutexedHandle::read`
+///
+/// The `R` is the type of the value returned by the closure of `call`.
+#[derive(Debug)]
+pub enum CallError<R=()> {
+    /// The lock is poisoned, the state machine is waiting for a restart
     ///
-    /// **The method must be cheap to compute**. Because it's executed on
-    /// every action of a state machine (for `mem::replace`)
+    /// The closure is not called in this case, but the state machine is
+    /// woken up, so it's restarted as soon as possible.
+    Poisoned,
+    /// The state machine has been stopped, the closure is not called
+    Stopped,
+    /// The closure was called but the state machine could not be woken up
     ///
-    /// Note in case the lock is poisoned (panic was received while keeping
-    /// state machine locked, perhaps in another thread), the `empty` value
-    /// is used on the poisoned value, before `restart()` is called. This
-    /// means you only want to use read-only parts of `self` here (better
-    /// none at all, but that's not always possible).
-    fn empty(&self) -> Self;
-    /// Restart a state machine from `empty()` state
+    /// Changes made by the closure are applied, and the value it returned
+    /// is here.
+    Wakeup(R, WakeupError),
+}
+
+/// A trait which allows to restart the state machine after a crash
+///
+/// Machine used with `Mutexed` is kept in an `Arc<Mutex<Option<M>>>`. The
+/// machine is moved out of the lock while any of its event handlers is
+/// running, and put back afterwards.
+///
+/// When the thread working with the state machine panics while keeping the
+/// lock, the lock is poisoned. If the panic happened in the event handler
+/// of the machine, there is no machine in the lock any more. If the panic
+/// happened in other thread (i.e. in `MutexedHandle::call`) the machine is
+/// still there, but may be left in an inconsistent state. In both cases
+/// `restart()` is called, so you can continue to work after panic with the
+/// clean state.
+pub trait Replaceable: Machine {
+    /// Restart a state machine after the lock was poisoned
     ///
     /// This method is called before calling any other action methods when
-    /// lock holding the state machine was poisoned.
+    /// lock holding the state machine was poisoned. The `previous` is the
+    /// machine that was left in the lock, if any.
     ///
     /// Note that after the `restart` current event is discarded, it's assumed
     /// that state machine is already arranged to receive some new events
     /// (i.e. it's useless to keep old `ready()` event if new connection is
-    /// just being established)
+    /// just being established). The discarded event is passed to the
+    /// `poisoned()` hook. Use `RestartPolicy::replay` to deliver the event
+    /// to the restarted machine instead.
     ///
-    /// While you can check the state of the old machine (a `self`), and even
-    /// return it as is, it's strongly discouraged, as you can't know exact
-    /// kind of failure that happened in other thread (when lock was
+    /// While you can check the state of the old machine (a `previous`), and
+    /// even return it as is, it's strongly discouraged, as you can't know
+    /// exact kind of failure that happened in other thread (when lock was
     /// poisoned). But in case protocol is super-simple (like line-based
     /// without exceptions) and it's not security critical (i.e. monitoring
     /// using graphite), you may reuse old state machine or parts there of.
     ///
     /// Default implementation is just to panic
-    fn restart(self, _scope: &mut Scope<Self::Context>)
+    fn restart(_previous: Option<Self>, _scope: &mut Scope<Self::Context>)
         -> Response<Self, Self::Seed>
     {
         panic!("State machine has been poisoned");
     }
+    /// Returns the policy used to restart the state machine
+    ///
+    /// It's used for the `Mutexed` created by `Mutexed::new` or by
+    /// `Machine::create`. Default is to restart every time.
+    fn restart_policy() -> RestartPolicy {
+        RestartPolicy::default()
+    }
+    /// Called when the poisoned lock is found, before the restart
+    ///
+    /// This is the place to log and alert on crashes. It's called for every
+    /// event discarded because of the poisoned lock, including ones that
+    /// are discarded while waiting for the restart backoff. Note that the
+    /// machine itself is not available here.
+    ///
+    /// Default implementation does nothing
+    fn poisoned(_info: &PoisonInfo, _scope: &mut Scope<Self::Context>) {
+    }
+}
+
+impl PoisonInfo {
+    /// The event which is discarded
+    pub fn event(&self) -> Event {
+        self.event
+    }
+    /// The name (or id if thread is unnamed) of the thread which has
+    /// panicked while holding the lock, if known
+    pub fn thread(&self) -> Option<&str> {
+        self.thread.as_ref().map(|x| &x[..])
+    }
+    /// The number of this poisoning, i.e. `1` before the first restart
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl RestartPolicy {
+    /// Restart without limits, the same as `RestartPolicy::default()`
+    pub fn new() -> RestartPolicy {
+        RestartPolicy {
+            limit: None,
+            backoff: Duration::new(0, 0),
+            escalate: Escalate::Stop,
+            replay: false,
+        }
+    }
+    /// Allow at most `restarts` restarts per time `window`
+    pub fn max_restarts(mut self, restarts: usize, window: Duration)
+        -> RestartPolicy
+    {
+        self.limit = Some((restarts, window));
+        self
+    }
+    /// Wait at least `delay` after the previous restart
+    ///
+    /// While waiting, all events of the state machine are ignored, and the
+    /// deadline is set to the time of the next restart.
+    pub fn backoff(mut self, delay: Duration) -> RestartPolicy {
+        self.backoff = delay;
+        self
+    }
+    /// Set what to do when more than `max_restarts` are needed
+    pub fn escalate(mut self, escalate: Escalate) -> RestartPolicy {
+        self.escalate = escalate;
+        self
+    }
+    /// Deliver the discarded event to the machine after `restart()`
+    ///
+    /// By default the event which has found the poisoned lock is discarded.
+    /// With replay enabled, the event (`ready()` with the same `EventSet`,
+    /// `timeout()`, `wakeup()` or `spawned()`) is delivered to the machine
+    /// returned by `restart()`, and the response of the event handler
+    /// replaces the response of `restart()`, including the deadline.
+    ///
+    /// The event is not replayed if `restart()` has stopped the machine or
+    /// has spawned a new one. When the restart is delayed by `backoff`, the
+    /// first event discarded while waiting is replayed, rather than the
+    /// timeout of the backoff. Other discarded events are not replayed.
+    pub fn replay(mut self, replay: bool) -> RestartPolicy {
+        self.replay = replay;
+        self
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy::new()
+    }
+}
+
+impl Supervisor {
+    fn new(policy: RestartPolicy) -> Supervisor {
+        Supervisor {
+            policy,
+            recent: VecDeque::new(),
+            last_restart: None,
+            delayed: None,
+        }
+    }
+    /// Decides what to do with the poisoned machine on `event`
+    ///
+    /// When the restart is delayed, the first discarded event is kept, and
+    /// returned instead of the (synthetic) timeout of the backoff.
+    fn check(&mut self, now: Time, event: Event) -> Verdict {
+        if let Some(last) = self.last_restart {
+            let restart_at = last + self.policy.backoff;
+            if now < restart_at {
+                if self.delayed.is_none() {
+                    self.delayed = Some(event);
+                }
+                return Verdict::Delay(restart_at);
+            }
+        }
+        let event = self.delayed.take().unwrap_or(event);
+        if let Some((restarts, window)) = self.policy.limit {
+            while let Some(&first) = self.recent.front() {
+                if first + window > now {
+                    break;
+                }
+                self.recent.pop_front();
+            }
+            if self.recent.len() >= restarts {
+                return Verdict::Escalate;
+            }
+            self.recent.push_back(now);
+        }
+        self.last_restart = Some(now);
+        Verdict::Restart(event)
+    }
+}
+
+impl<M: Replaceable> Mutexed<M> {
+    /// Wraps the state machine, using `Replaceable::restart_policy`
+    pub fn new(machine: M) -> Mutexed<M> {
+        Mutexed {
+            shared: Shared::new(Mutex::new(Some(machine))),
+            supervisor: Supervisor::new(M::restart_policy()),
+        }
+    }
+}
+
+impl<M> Mutexed<M> {
+    /// Replaces the restart policy of this state machine
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Mutexed<M> {
+        self.supervisor = Supervisor::new(policy);
+        self
+    }
+    /// Number of times the state machine has been restarted
+    pub fn restarts(&self) -> usize {
+        self.shared.restarts.load(Ordering::Relaxed)
+    }
+    /// Creates a handle to use the state machine from other threads
+    ///
+    /// The `notifier` must belong to this state machine.
+    pub fn handle<N: GetNotifier>(&self, notifier: N) -> MutexedHandle<M> {
+        MutexedHandle {
+            shared: self.shared.clone(),
+            notifier: notifier.get_notifier(),
+        }
+    }
+}
+
+impl<M> MutexedHandle<M> {
+    /// Runs `fun` with the state machine locked and wakes the machine up
+    ///
+    /// If the loop of the state machine is already shut down, the wakeup
+    /// error is ignored, as there is nobody to act on the changes anyway.
+    pub fn call<R, F>(&self, fun: F) -> Result<R, CallError<R>>
+        where F: FnOnce(&mut M) -> R
+    {
+        let result = {
+            let mut guard = lock(&self.shared.machine);
+            if self.shared.is_poisoned() {
+                self.notifier.wakeup().ok();
+                return Err(CallError::Poisoned);
+            }
+            match *guard {
+                Some(ref mut machine) => {
+                    let _panic = PanicGuard(&self.shared);
+                    fun(machine)
+                }
+                None => return Err(CallError::Stopped),
+            }
+        };
+        match self.notifier.wakeup() {
+            Ok(()) | Err(WakeupError::Closed) => Ok(result),
+            Err(e) => Err(CallError::Wakeup(result, e)),
+        }
+    }
+    /// Number of times the state machine has been restarted
+    pub fn restarts(&self) -> usize {
+        self.shared.restarts.load(Ordering::Relaxed)
+    }
+}
+
+impl<M> Clone for MutexedHandle<M> {
+    fn clone(&self) -> MutexedHandle<M> {
+        MutexedHandle {
+            shared: self.shared.clone(),
+            notifier: self.notifier.clone(),
+        }
+    }
+}
+
+impl<M: Replaceable> RwMutexed<M> {
+    /// Wraps the state machine, using `Replaceable::restart_policy`
+    pub fn new(machine: M) -> RwMutexed<M> {
+        RwMutexed {
+            shared: Shared::new(RwLock::new(Some(machine))),
+            supervisor: Supervisor::new(M::restart_policy()),
+        }
+    }
+}
+
+impl<M> RwMutexed<M> {
+    /// Replaces the restart policy of this state machine
+    pub fn with_policy(mut self, policy: RestartPolicy) -> RwMutexed<M> {
+        self.supervisor = Supervisor::new(policy);
+        self
+    }
+    /// Number of times the state machine has been restarted
+    pub fn restarts(&self) -> usize {
+        self.shared.restarts.load(Ordering::Relaxed)
+    }
+    /// Creates a handle to read the state machine from other threads
+    ///
+    /// The `notifier` must belong to this state machine.
+    pub fn handle<N: GetNotifier>(&self, notifier: N) -> RwMutexedHandle<M> {
+        RwMutexedHandle {
+            shared: self.shared.clone(),
+            notifier: notifier.get_notifier(),
+        }
+    }
+}
+
+impl<M> RwMutexedHandle<M> {
+    /// Locks the state machine for reading
+    ///
+    /// If the lock is poisoned the state machine is woken up to be restarted
+    /// as soon as possible.
+    pub fn read(&self) -> Result<ReadGuard<'_, M>, CallError> {
+        let guard = self.shared.machine.read()
+            .unwrap_or_else(|e| e.into_inner());
+        if self.shared.is_poisoned() {
+            self.notifier.wakeup().ok();
+            return Err(CallError::Poisoned);
+        }
+        if guard.is_none() {
+            return Err(CallError::Stopped);
+        }
+        Ok(ReadGuard(guard))
+    }
+    /// Number of times the state machine has been restarted
+    pub fn restarts(&self) -> usize {
+        self.shared.restarts.load(Ordering::Relaxed)
+    }
+}
+
+impl<M> Clone for RwMutexedHandle<M> {
+    fn clone(&self) -> RwMutexedHandle<M> {
+        RwMutexedHandle {
+            shared: self.shared.clone(),
+            notifier: self.notifier.clone(),
+        }
+    }
+}
+
+impl<'a, M> Deref for ReadGuard<'a, M> {
+    type Target = M;
+    fn deref(&self) -> &M {
+        self.0.as_ref().expect("machine is checked in read()")
+    }
+}
+
+impl<M> Lock for Mutex<M> {
+    type Machine = M;
+    fn locked<R, F>(&self, fun: F) -> R
+        where F: FnOnce(&mut M) -> R
+    {
+        fun(&mut *lock(self))
+    }
+}
+
+impl<M> Lock for RwLock<M> {
+    type Machine = M;
+    fn locked<R, F>(&self, fun: F) -> R
+        where F: FnOnce(&mut M) -> R
+    {
+        fun(&mut *self.write().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl<L> Shared<L> {
+    fn new(machine: L) -> Arc<Shared<L>> {
+        Arc::new(Shared {
+            machine,
+            poisoned: AtomicBool::new(false),
+            restarts: AtomicUsize::new(0),
+            poisoned_by: Mutex::new(None),
+        })
+    }
+    fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+    fn poison_info(&self, event: Event) -> PoisonInfo {
+        PoisonInfo {
+            event,
+            thread: lock(&self.poisoned_by).clone(),
+            count: self.restarts.load(Ordering::Relaxed) + 1,
+        }
+    }
+}
+
+impl<'a, L> Drop for PanicGuard<'a, L> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let current = thread::current();
+            let name = match current.name() {
+                Some(name) => name.to_string(),
+                None => format!("{:?}", current.id()),
+            };
+            *lock(&self.0.poisoned_by) = Some(name);
+            self.0.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<R> fmt::Display for CallError<R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallError::Poisoned => write!(fmt, "state machine is poisoned"),
+            CallError::Stopped => write!(fmt, "state machine is stopped"),
+            CallError::Wakeup(_, ref e) => {
+                write!(fmt, "can't wake up state machine: {}", e)
+            }
+        }
+    }
+}
+
+impl<R: fmt::Debug> Error for CallError<R> {}
+
+fn dispatch<M: Machine>(fsm: M, event: Event, scope: &mut Scope<M::Context>)
+    -> Response<M, M::Seed>
+{
+    match event {
+        Event::Ready(events) => fsm.ready(events, scope),
+        Event::Spawned => fsm.spawned(scope),
+        Event::Timeout => fsm.timeout(scope),
+        Event::Wakeup => fsm.wakeup(scope),
+    }
+}
+
+fn replay<M: Machine>(res: Response<M, M::Seed>, event: Event,
+    scope: &mut Scope<M::Context>)
+    -> Response<M, M::Seed>
+{
+    let mut spawned = false;
+    let res = res.map(|m| m, |seed| { spawned = true; seed });
+    if spawned || res.is_stopped() {
+        return res;
+    }
+    let mut machine = None;
+    res.wrap(|m| machine = Some(m));
+    dispatch(machine.expect("response contains a machine"), event, scope)
 }
 
 #[inline]
-fn locked_call<M, F>(scope: &mut Scope<M::Context>, me: Mutexed<M>,
-    fun: F)
-    -> Response<Mutexed<M>, M::Seed>
-    where M: Replaceable,
-          F: FnOnce(M, &mut Scope<M::Context>) -> Response<M, M::Seed>
+fn locked_call<L, M>(scope: &mut Scope<M::Context>,
+    shared: &Shared<L>, supervisor: &mut Supervisor, mut event: Event)
+    -> Response<(), M::Seed>
+    where L: Lock<Machine=Option<M>>, M: Replaceable
 {
-    let fake_result = match me.0.lock() {
-        Ok(mut guard) => {
-            let empty = guard.empty();
-            let fsm = mem::replace(&mut *guard, empty);
-            let res = fun(fsm, scope);
-            res.wrap(|new_machine| {
-                // thows off an `empty()` instance
-                mem::replace(&mut *guard, new_machine);
-                ()
-            })
-        }
-        Err(poisoned) => {
-            let mut guard = poisoned.into_inner();
-            let empty = guard.empty();
-            let fsm = mem::replace(&mut *guard, empty);
-            let res = fsm.restart(scope);
-            res.wrap(|new_machine| {
-                // thows off an `empty()` instance
-                mem::replace(&mut *guard, new_machine);
-                ()
-            })
-        }
-    };
-    fake_result.wrap(|()| me)
+    let reported = shared.is_poisoned();
+    if reported {
+        M::poisoned(&shared.poison_info(event), scope);
+        // the lock may also get poisoned right after the check, in this case
+        // machine is restarted without consulting the policy
+        match supervisor.check(scope.now(), event) {
+            Verdict::Restart(first) => event = first,
+            Verdict::Delay(restart_at) => {
+                return Response::ok(()).deadline(restart_at);
+            }
+            Verdict::Escalate => match supervisor.policy.escalate {
+                Escalate::Stop => {
+                    // handles get `Stopped` instead of `Poisoned` from now on
+                    shared.machine.locked(|slot| slot.take());
+                    shared.poisoned.store(false, Ordering::Relaxed);
+                    return Response::done();
+                }
+                Escalate::Panic => {
+                    panic!("State machine has been restarted too often");
+                }
+            },
+        }
+    }
+    let replay_event = supervisor.policy.replay;
+    shared.machine.locked(|slot| {
+        let poisoned = shared.is_poisoned();
+        let _panic = PanicGuard(shared);
+        let res = if poisoned {
+            if !reported {
+                M::poisoned(&shared.poison_info(event), scope);
+            }
+            let res = M::restart(slot.take(), scope);
+            shared.poisoned.store(false, Ordering::Relaxed);
+            shared.restarts.fetch_add(1, Ordering::Relaxed);
+            *lock(&shared.poisoned_by) = None;
+            if replay_event {
+                replay(res, event, scope)
+            } else {
+                res
+            }
+        } else {
+            let fsm = slot.take().expect("events are not delivered to \
+                the stopped state machine");
+            dispatch(fsm, event, scope)
+        };
+        res.wrap(|new_machine| {
+            *slot = Some(new_machine);
+        })
+    })
 }
 
 impl<M: Replaceable> Machine for Mutexed<M> {
@@ -100,26 +636,265 @@ impl<M: Replaceable> Machine for Mutexed<M> {
     fn create(seed: Self::Seed, scope: &mut Scope<M::Context>)
         -> Response<Self, Void>
     {
-        M::create(seed, scope).wrap(Mutex::new).wrap(Arc::new).wrap(Mutexed)
+        M::create(seed, scope).wrap(Mutexed::new)
+    }
+    fn ready(mut self, events: EventSet, scope: &mut Scope<M::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Ready(events)).wrap(|()| self)
+    }
+    fn spawned(mut self, scope: &mut Scope<M::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Spawned).wrap(|()| self)
+    }
+    fn timeout(mut self, scope: &mut Scope<M::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Timeout).wrap(|()| self)
+    }
+    fn wakeup(mut self, scope: &mut Scope<M::Context>)
+        -> Response<Self, Self::Seed>
+    {
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Wakeup).wrap(|()| self)
+    }
+}
+
+impl<M: Replaceable> Machine for RwMutexed<M> {
+    type Context = M::Context;
+    type Seed = M::Seed;
+    fn create(seed: Self::Seed, scope: &mut Scope<M::Context>)
+        -> Response<Self, Void>
+    {
+        M::create(seed, scope).wrap(RwMutexed::new)
     }
-    fn ready(self, events: EventSet, scope: &mut Scope<M::Context>)
+    fn ready(mut self, events: EventSet, scope: &mut Scope<M::Context>)
         -> Response<Self, Self::Seed>
     {
-        locked_call(scope, self, |fsm, scope| fsm.ready(events, scope))
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Ready(events)).wrap(|()| self)
     }
-    fn spawned(self, scope: &mut Scope<M::Context>)
+    fn spawned(mut self, scope: &mut Scope<M::Context>)
         -> Response<Self, Self::Seed>
     {
-        locked_call(scope, self, |fsm, scope| fsm.spawned(scope))
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Spawned).wrap(|()| self)
     }
-    fn timeout(self, scope: &mut Scope<M::Context>)
+    fn timeout(mut self, scope: &mut Scope<M::Context>)
         -> Response<Self, Self::Seed>
     {
-        locked_call(scope, self, |fsm, scope| fsm.timeout(scope))
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Timeout).wrap(|()| self)
     }
-    fn wakeup(self, scope: &mut Scope<M::Context>)
+    fn wakeup(mut self, scope: &mut Scope<M::Context>)
         -> Response<Self, Self::Seed>
     {
-        locked_call(scope, self, |fsm, scope| fsm.wakeup(scope))
+        locked_call(scope, &self.shared, &mut self.supervisor,
+            Event::Wakeup).wrap(|()| self)
+    }
+}
+
+#[cfg(test)]
+// `const` initializer in `thread_local!` needs newer Rust
+#[allow(clippy::missing_const_for_thread_local)]
+mod test {
+    extern crate rotor_test;
+
+    use std::thread;
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    use rotor::{Machine, EventSet, Scope, Response, Time};
+    use rotor::void::{Void, unreachable};
+
+    use super::{Mutexed, MutexedHandle, RwMutexed, Replaceable, CallError};
+    use super::{RestartPolicy, PoisonInfo, Event, Supervisor, Verdict};
+    use util::test::machine;
+
+    thread_local! {
+        static POISONED: RefCell<Vec<(Event, Option<String>, usize)>>
+            = RefCell::new(Vec::new());
+    }
+
+    /// Counts wakeups, so we know when machine is restarted
+    #[derive(Debug, PartialEq)]
+    struct Counter(u32);
+
+    impl Machine for Counter {
+        type Context = ();
+        type Seed = Void;
+        fn create(seed: Void, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            unreachable(seed)
+        }
+        fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Counter(self.0 + 100))
+        }
+        fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            unreachable!();
+        }
+        fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Counter(self.0 + 10))
+        }
+        fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+            Response::ok(Counter(self.0 + 1))
+        }
+    }
+
+    impl Replaceable for Counter {
+        fn restart(_previous: Option<Self>, _scope: &mut Scope<()>)
+            -> Response<Self, Void>
+        {
+            Response::ok(Counter(1000))
+        }
+        fn poisoned(info: &PoisonInfo, _scope: &mut Scope<()>) {
+            POISONED.with(|p| p.borrow_mut().push((info.event(),
+                info.thread().map(String::from), info.count())));
+        }
+    }
+
+    fn mutexed(value: u32) -> Mutexed<Counter> {
+        Mutexed::new(Counter(value))
+    }
+
+    fn poison(handle: &MutexedHandle<Counter>) {
+        let handle = handle.clone();
+        thread::Builder::new().name("worker".into()).spawn(move || {
+            handle.call(|_| panic!("poison the lock")).unwrap();
+        }).unwrap().join().unwrap_err();
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = mutexed(5);
+        let handle = fsm.handle(&mut lp.scope(1));
+        assert_eq!(handle.call(|m| { m.0 += 2; m.0 }).unwrap(), 7);
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        poison(&handle);
+        match handle.call(|m| m.0) {
+            Err(CallError::Poisoned) => {}
+            _ => panic!("lock must be poisoned"),
+        }
+        machine(fsm.wakeup(&mut lp.scope(1)));
+        assert_eq!(handle.call(|m| m.0).unwrap(), 1000);
+    }
+
+    #[test]
+    fn test_max_restarts() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = mutexed(5).with_policy(RestartPolicy::new()
+            .max_restarts(2, Duration::from_secs(60)));
+        let handle = fsm.handle(&mut lp.scope(1));
+        poison(&handle);
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        assert_eq!(fsm.restarts(), 1);
+        poison(&handle);
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        assert_eq!(handle.restarts(), 2);
+        poison(&handle);
+        assert!(fsm.wakeup(&mut lp.scope(1)).is_stopped());
+        assert_eq!(handle.restarts(), 2);
+        match handle.call(|m| m.0) {
+            Err(CallError::Stopped) => {}
+            _ => panic!("machine must be stopped"),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = mutexed(5).with_policy(RestartPolicy::new()
+            .backoff(Duration::from_secs(1)));
+        let handle = fsm.handle(&mut lp.scope(1));
+        poison(&handle);
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        assert_eq!(handle.call(|m| m.0).unwrap(), 1000);
+        poison(&handle);
+        // time doesn't advance in the mock loop, so restart is postponed
+        let fsm = machine(fsm.timeout(&mut lp.scope(1)));
+        assert_eq!(fsm.restarts(), 1);
+        match handle.call(|m| m.0) {
+            Err(CallError::Poisoned) => {}
+            _ => panic!("lock must be poisoned"),
+        }
+    }
+
+    #[test]
+    fn test_poisoned_hook() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = mutexed(5);
+        let handle = fsm.handle(&mut lp.scope(1));
+        poison(&handle);
+        let fsm = machine(fsm.timeout(&mut lp.scope(1)));
+        poison(&handle);
+        machine(fsm.ready(EventSet::readable(), &mut lp.scope(1)));
+        POISONED.with(|p| assert_eq!(*p.borrow(), vec![
+            (Event::Timeout, Some("worker".to_string()), 1),
+            (Event::Ready(EventSet::readable()), Some("worker".to_string()), 2),
+        ]));
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = mutexed(5).with_policy(RestartPolicy::new().replay(true));
+        let handle = fsm.handle(&mut lp.scope(1));
+        poison(&handle);
+        machine(fsm.timeout(&mut lp.scope(1)));
+        assert_eq!(handle.call(|m| m.0).unwrap(), 1010);
+    }
+
+    #[test]
+    fn test_replay_after_backoff() {
+        // time doesn't advance in the mock loop, so supervisor is driven
+        // directly
+        let mut supervisor = Supervisor::new(RestartPolicy::new()
+            .backoff(Duration::from_secs(1)).replay(true));
+        let start = Time::zero();
+        match supervisor.check(start, Event::Wakeup) {
+            Verdict::Restart(Event::Wakeup) => {}
+            _ => panic!("first restart is immediate"),
+        }
+        let ready = Event::Ready(EventSet::readable());
+        let restart_at = match supervisor.check(
+            start + Duration::from_millis(10), ready)
+        {
+            Verdict::Delay(time) => time,
+            _ => panic!("restart must be delayed"),
+        };
+        match supervisor.check(start + Duration::from_millis(20),
+            Event::Wakeup)
+        {
+            Verdict::Delay(time) => assert_eq!(time, restart_at),
+            _ => panic!("restart must be delayed"),
+        }
+        match supervisor.check(restart_at, Event::Timeout) {
+            Verdict::Restart(event) => assert_eq!(event, ready),
+            _ => panic!("restart must happen at the deadline"),
+        }
+    }
+
+    #[test]
+    fn test_rw_read() {
+        let mut lp = rotor_test::MockLoop::new(());
+        let fsm = RwMutexed::new(Counter(5));
+        let handle = fsm.handle(&mut lp.scope(1));
+        let fsm = machine(fsm.wakeup(&mut lp.scope(1)));
+        let reader = handle.clone();
+        {
+            let first = handle.read().unwrap();
+            let second = reader.read().unwrap();
+            assert_eq!(*first, Counter(6));
+            assert_eq!(*second, Counter(6));
+        }
+        machine(fsm.timeout(&mut lp.scope(1)));
+        assert_eq!(*handle.read().unwrap(), Counter(16));
+        assert_eq!(handle.restarts(), 0);
     }
 }
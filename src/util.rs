@@ -0,0 +1,25 @@
+use std::sync::{Mutex, MutexGuard};
+
+
+/// Locks the mutex ignoring the poisoning
+///
+/// Only use it for the data which is consistent at any point, so it's safe
+/// to use after a panic in another thread that held the lock.
+pub(crate) fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use rotor::Response;
+
+    /// Returns the machine from the response, panics if it's stopped
+    ///
+    /// Unlike `Response::expect_machine` it doesn't need `Debug`, which
+    /// most wrapping state machines don't implement.
+    pub(crate) fn machine<M, N>(response: Response<M, N>) -> M {
+        let mut result = None;
+        response.wrap(|m| result = Some(m));
+        result.expect("response contains a machine")
+    }
+}
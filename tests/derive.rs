@@ -0,0 +1,79 @@
+#![cfg(feature="derive")]
+extern crate rotor;
+extern crate rotor_test;
+extern crate rotor_tools;
+
+use rotor::{Machine, EventSet, Scope, Response};
+use rotor::void::{Void, unreachable};
+use rotor_tools::sync::Replaceable;
+
+
+#[derive(Debug, PartialEq, Replaceable)]
+enum Fsm {
+    #[replaceable(restart)]
+    Idle,
+    Busy(u32),
+}
+
+#[derive(Debug, PartialEq, Replaceable)]
+enum Generic<T> {
+    #[replaceable(restart)]
+    Idle,
+    #[allow(dead_code)]
+    Value(T),
+}
+
+impl Machine for Fsm {
+    type Context = ();
+    type Seed = Void;
+    fn create(seed: Void, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable(seed)
+    }
+    fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+        -> Response<Self, Void>
+    {
+        Response::ok(Fsm::Busy(1))
+    }
+    fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+    fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+    fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+}
+
+impl<T> Machine for Generic<T> {
+    type Context = ();
+    type Seed = Void;
+    fn create(seed: Void, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable(seed)
+    }
+    fn ready(self, _events: EventSet, _scope: &mut Scope<()>)
+        -> Response<Self, Void>
+    {
+        unreachable!();
+    }
+    fn spawned(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+    fn timeout(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+    fn wakeup(self, _scope: &mut Scope<()>) -> Response<Self, Void> {
+        unreachable!();
+    }
+}
+
+#[test]
+fn test_derive() {
+    let mut lp = rotor_test::MockLoop::new(());
+    let restarted = Fsm::restart(Some(Fsm::Busy(7)), &mut lp.scope(1))
+        .expect_machine();
+    assert_eq!(restarted, Fsm::Idle);
+    let restarted = Generic::<u32>::restart(None, &mut lp.scope(1))
+        .expect_machine();
+    assert_eq!(restarted, Generic::Idle);
+}